@@ -0,0 +1,293 @@
+use intcode::IntcodeProgram;
+use std::{ops::Range, thread};
+
+/// The values tried for the noun and the verb
+pub const INPUT_RANGE: Range<isize> = 0..100;
+
+/// How far past the end of the program the symbolic solver follows writes before
+/// leaving the program to the brute force search
+pub const MAX_GROWTH: usize = 1024;
+
+/// A value of the form `constant + noun * self.noun + verb * self.verb`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Linear {
+    pub constant: isize,
+    pub noun: isize,
+    pub verb: isize,
+}
+
+impl Linear {
+    fn constant(value: isize) -> Self {
+        Self {
+            constant: value,
+            noun: 0,
+            verb: 0,
+        }
+    }
+
+    fn as_constant(&self) -> Option<isize> {
+        if self.noun == 0 && self.verb == 0 {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    fn add(&self, other: &Self) -> Option<Self> {
+        Some(Self {
+            constant: self.constant.checked_add(other.constant)?,
+            noun: self.noun.checked_add(other.noun)?,
+            verb: self.verb.checked_add(other.verb)?,
+        })
+    }
+
+    fn scale(&self, factor: isize) -> Option<Self> {
+        Some(Self {
+            constant: self.constant.checked_mul(factor)?,
+            noun: self.noun.checked_mul(factor)?,
+            verb: self.verb.checked_mul(factor)?,
+        })
+    }
+
+    /// Only products where one side is constant stay linear
+    fn mul(&self, other: &Self) -> Option<Self> {
+        match (self.as_constant(), other.as_constant()) {
+            (Some(factor), _) => other.scale(factor),
+            (_, Some(factor)) => self.scale(factor),
+            _ => None,
+        }
+    }
+}
+
+/// A memory cell during symbolic execution. `Unknown` cells came from something the
+/// solver can't express, and only matter if they're used as an opcode, an address or
+/// the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    Known(Linear),
+    Unknown,
+}
+
+impl Cell {
+    fn combine<F: Fn(&Linear, &Linear) -> Option<Linear>>(self, other: Self, f: F) -> Self {
+        match (self, other) {
+            (Cell::Known(a), Cell::Known(b)) => f(&a, &b).map_or(Cell::Unknown, Cell::Known),
+            _ => Cell::Unknown,
+        }
+    }
+
+    fn as_constant(&self) -> Option<isize> {
+        match self {
+            Cell::Known(linear) => linear.as_constant(),
+            Cell::Unknown => None,
+        }
+    }
+}
+
+/// Reads the cell a position mode parameter points at. Reading through an address that
+/// depends on the inputs gives `Unknown`, and reading a negative address fails.
+fn read(memory: &[Cell], parameter: &Cell) -> Option<Cell> {
+    match parameter.as_constant() {
+        Some(address) if address < 0 => None,
+        Some(address) => Some(
+            memory
+                .get(address as usize)
+                .copied()
+                .unwrap_or(Cell::Known(Linear::constant(0))),
+        ),
+        None => Some(Cell::Unknown),
+    }
+}
+
+/// Runs the program with the noun and verb left as unknowns, returning the value left
+/// at position 0 as a linear expression over them.
+///
+/// Only the add, multiply and halt opcodes of day 2 are understood. Returns `None` if
+/// the program does anything else, writes more than `MAX_GROWTH` cells past its end,
+/// or if control flow, an address or the result depends on the inputs in a way that
+/// isn't linear.
+pub fn symbolic_result(program: &IntcodeProgram) -> Option<Linear> {
+    let mut memory: Vec<Cell> = program
+        .memory()
+        .iter()
+        .map(|value| Cell::Known(Linear::constant(*value)))
+        .collect();
+    let max_len = memory.len() + MAX_GROWTH;
+    if memory.len() < 3 {
+        return None;
+    }
+    memory[1] = Cell::Known(Linear {
+        constant: 0,
+        noun: 1,
+        verb: 0,
+    });
+    memory[2] = Cell::Known(Linear {
+        constant: 0,
+        noun: 0,
+        verb: 1,
+    });
+
+    let mut exec_ptr = 0;
+    loop {
+        match memory.get(exec_ptr)?.as_constant()? {
+            op @ 1 | op @ 2 => {
+                let a = read(&memory, memory.get(exec_ptr + 1)?)?;
+                let b = read(&memory, memory.get(exec_ptr + 2)?)?;
+                let target = memory.get(exec_ptr + 3)?.as_constant()?;
+                if target < 0 {
+                    return None;
+                }
+                let target = target as usize;
+                if target >= max_len {
+                    return None;
+                }
+                if target >= memory.len() {
+                    memory.resize(target + 1, Cell::Known(Linear::constant(0)));
+                }
+                memory[target] = if op == 1 {
+                    a.combine(b, Linear::add)
+                } else {
+                    a.combine(b, Linear::mul)
+                };
+                exec_ptr += 4;
+            }
+            99 => break,
+            _ => return None,
+        }
+    }
+
+    match memory[0] {
+        Cell::Known(linear) => Some(linear),
+        Cell::Unknown => None,
+    }
+}
+
+/// Finds the first noun, then verb, in `INPUT_RANGE` for which the expression gives
+/// `target`
+pub fn solve_linear(linear: &Linear, target: isize) -> Option<(isize, isize)> {
+    INPUT_RANGE.into_iter().find_map(|noun| {
+        let remaining = target
+            .checked_sub(linear.constant)?
+            .checked_sub(linear.noun.checked_mul(noun)?)?;
+        let verb = if linear.verb == 0 {
+            if remaining != 0 {
+                return None;
+            }
+            INPUT_RANGE.start
+        } else if remaining % linear.verb == 0 {
+            remaining / linear.verb
+        } else {
+            return None;
+        };
+        if INPUT_RANGE.contains(&verb) {
+            Some((noun, verb))
+        } else {
+            None
+        }
+    })
+}
+
+/// The value left at position 0, or `None` if the program fails for these inputs
+fn try_run(program: &IntcodeProgram, noun: isize, verb: isize) -> Option<isize> {
+    let mut program = program.clone();
+    program.set_value(1, noun);
+    program.set_value(2, verb);
+    program.run_to_halt().ok()?;
+    Some(program.memory()[0])
+}
+
+/// Tries every noun and verb, splitting the nouns between a thread per core. Returns
+/// the same pair a sequential search would find first.
+pub fn brute_force(program: &IntcodeProgram, target: isize) -> Option<(isize, isize)> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let nouns: Vec<isize> = INPUT_RANGE.collect();
+    let chunk_size = nouns.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = nouns
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk.iter().find_map(|noun| {
+                        INPUT_RANGE
+                            .into_iter()
+                            .find(|verb| try_run(program, *noun, *verb) == Some(target))
+                            .map(|verb| (*noun, verb))
+                    })
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().expect("Search thread panicked"))
+            .min()
+    })
+}
+
+/// Finds a noun and verb for which the program leaves `target` at position 0, solving
+/// symbolically when the program is linear in them and searching otherwise.
+///
+/// A symbolic answer is checked by running the program, in case it reads through an
+/// address that only fails for some inputs.
+pub fn solve(program: &IntcodeProgram, target: isize) -> Option<(isize, isize)> {
+    match symbolic_result(program) {
+        Some(linear) => match solve_linear(&linear, target) {
+            Some((noun, verb)) if try_run(program, noun, verb) == Some(target) => {
+                Some((noun, verb))
+            }
+            Some(_) => brute_force(program, target),
+            None => None,
+        },
+        None => brute_force(program, target),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn linear_program() {
+        // [0] = noun * 3 + verb + 7. The first instruction reads through the noun and
+        // verb as addresses, but its result is overwritten.
+        let program = IntcodeProgram::new(vec![
+            1, 0, 0, 3, 2, 1, 17, 3, 1, 3, 2, 3, 1, 3, 18, 0, 99, 3, 7,
+        ]);
+        let linear = symbolic_result(&program).unwrap();
+        assert_eq!(
+            linear,
+            Linear {
+                constant: 7,
+                noun: 3,
+                verb: 1
+            }
+        );
+        assert_eq!(crate::run(&program, 12, 2).0, 45);
+
+        assert_eq!(solve(&program, 132), Some((9, 98)));
+        assert_eq!(brute_force(&program, 132), Some((9, 98)));
+        assert_eq!(solve(&program, 1000), None);
+    }
+
+    #[test]
+    fn non_linear_program() {
+        // [0] = noun * verb
+        let program = IntcodeProgram::new(vec![1, 0, 0, 3, 2, 1, 2, 0, 99]);
+        assert_eq!(symbolic_result(&program), None);
+        assert_eq!(solve(&program, 408), Some((6, 68)));
+    }
+
+    #[test]
+    fn distant_write() {
+        // Writes just past the end are followed, but far-off ones are given up on. The
+        // first instruction reads through the noun and verb, as the puzzle's programs do.
+        let near = 15 + MAX_GROWTH as isize - 1;
+        let program =
+            IntcodeProgram::new(vec![1, 0, 0, 3, 1, 13, 14, near, 1, near, 14, 0, 99, 4, 5]);
+        assert_eq!(symbolic_result(&program), Some(Linear::constant(14)));
+
+        let program = IntcodeProgram::new(vec![1, 0, 0, 3, 1, 0, 0, 1_000_000_000_000, 99]);
+        assert_eq!(symbolic_result(&program), None);
+    }
+}
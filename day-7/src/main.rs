@@ -1,6 +1,4 @@
-mod intcode;
-
-use intcode::{IntcodeProgram, IntcodeResult};
+use intcode::{read_program, IntcodeProgram, IntcodeResult, NodeId, Pipeline};
 
 static INPUT_STR: &str = include_str!("../input.txt");
 
@@ -55,69 +53,53 @@ where
     best_output
 }
 
-fn read_program(program_str: &str) -> IntcodeProgram {
-    IntcodeProgram::new(
-        program_str
-            .split(',')
-            .map(|token| token.parse::<isize>().expect("Could not parse input token"))
-            .collect(),
-    )
-}
-
 pub fn run_amp_sequence(program: &IntcodeProgram, phase_settings: Vec<isize>) -> isize {
-    let mut piped_value = STARTING_INPUT;
-    for phase in phase_settings.into_iter() {
-        // IntcodeProgram treats inputs like a stack, so the phase goes at the end in
-        // order to be processed first
-        let inputs = vec![piped_value, phase];
-        if let IntcodeResult::Suspend(output) = program.clone().run(inputs) {
-            piped_value = output;
-        } else {
-            unreachable!("Program halted before outputting");
-        }
-    }
-
-    // This is the last output value we got
-    piped_value
+    run_amps(program, phase_settings, false)
 }
 
 pub fn run_feedback_loop(program: &IntcodeProgram, phase_settings: Vec<isize>) -> isize {
-    let amp_count = phase_settings.len();
+    run_amps(program, phase_settings, true)
+}
 
-    let mut amps = Vec::new();
-    for _ in 0..amp_count {
-        amps.push(program.clone());
+/// Chains a copy of the program per phase setting, optionally feeding the last amp's
+/// output back into the first, and returns the last signal out of the chain. With no
+/// amps the starting signal comes straight out.
+fn run_amps(program: &IntcodeProgram, phase_settings: Vec<isize>, feedback: bool) -> isize {
+    if phase_settings.is_empty() {
+        return STARTING_INPUT;
     }
-
-    let mut piped_value = STARTING_INPUT;
-    let mut phases_initialized = false;
-
-    loop {
-        for idx in 0..phase_settings.len() {
-            let inputs = if !phases_initialized {
-                // IntcodeProgram treats inputs like a stack, so the phase goes at the end in
-                // order to be processed first
-                vec![piped_value, phase_settings[idx]]
-            } else {
-                // After the first loop, the phases should not be provided
-                vec![piped_value]
-            };
-            let program_result = amps.get_mut(idx).unwrap().run(inputs);
-            if let IntcodeResult::Suspend(output) = program_result {
-                piped_value = output;
-            } else {
-                // This is the last output value we got
-                return piped_value;
-            }
-        }
-
-        phases_initialized = true;
+    let mut pipeline = Pipeline::new();
+    // Each amp reads its phase setting before its first signal
+    let amps: Vec<NodeId> = phase_settings
+        .into_iter()
+        .enumerate()
+        .map(|(index, phase)| {
+            let amp = pipeline.add_node(&format!("amp {}", index), program.clone());
+            pipeline.push_input(amp, phase);
+            amp
+        })
+        .collect();
+
+    for pair in amps.windows(2) {
+        pipeline.connect(pair[0], pair[1]);
     }
+    let first = amps[0];
+    let last = amps[amps.len() - 1];
+    if feedback {
+        pipeline.connect(last, first);
+    }
+    pipeline.push_input(first, STARTING_INPUT);
+
+    let report = pipeline.run().expect("Intcode program failed");
+    report
+        .last_output(last)
+        .expect("Program halted before outputting")
 }
 
 pub fn run_from_str(program_str: &str, input: Vec<isize>) -> IntcodeResult {
     let mut program = read_program(program_str);
-    program.run(input)
+    program.extend_inputs(input);
+    program.run().expect("Intcode program failed")
 }
 
 #[cfg(test)]
@@ -146,6 +128,20 @@ mod test {
         assert_eq!(run_amp_sequence(&program, vec![1, 0, 4, 3, 2]), 65210);
     }
 
+    #[test]
+    fn no_amps_or_one() {
+        let program = read_program("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+        assert_eq!(run_amp_sequence(&program, vec![]), STARTING_INPUT);
+        assert_eq!(run_amp_sequence(&program, vec![4]), 4);
+
+        // A single amp feeds itself: phase 9 turns each signal s into 2s + 5, five times
+        let program = read_program(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        );
+        assert_eq!(run_feedback_loop(&program, vec![]), STARTING_INPUT);
+        assert_eq!(run_feedback_loop(&program, vec![9]), 155);
+    }
+
     #[test]
     fn p2_example1() {
         let program = read_program(
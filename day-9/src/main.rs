@@ -1,6 +1,4 @@
-mod intcode;
-
-use intcode::{IntcodeProgram, IntcodeResult};
+use intcode::read_program;
 
 static INPUT_STR: &str = include_str!("../input.txt");
 
@@ -9,26 +7,10 @@ fn main() {
     println!("Problem 1:\n{:?}", run_from_str(INPUT_STR, vec![2]));
 }
 
-fn read_program(program_str: &str) -> IntcodeProgram {
-    IntcodeProgram::new(
-        program_str
-            .split(',')
-            .map(|token| token.parse::<isize>().expect("Could not parse input token"))
-            .collect(),
-    )
-}
-
 pub fn run_from_str(program_str: &str, input: Vec<isize>) -> Vec<isize> {
     let mut program = read_program(program_str);
-    let mut outputs = Vec::new();
-
-    let mut last_output = program.run(input);
-    while let IntcodeResult::Suspend(output_value) = last_output {
-        outputs.push(output_value);
-        last_output = program.run(vec![]);
-    }
-
-    outputs
+    program.extend_inputs(input);
+    program.run_to_halt().expect("Intcode program failed")
 }
 
 #[cfg(test)]
@@ -0,0 +1,102 @@
+//! Measures instruction decoding on the day 9 programs. Run with
+//! `cargo bench --bench decode`.
+
+use intcode::{
+    read_program, ArgMode, Instruction, IntcodeError, IntcodeProgram, MODE_IMM, MODE_POS, MODE_REL,
+};
+use std::time::{Duration, Instant};
+
+static DAY_9_INPUT: &str = include_str!("../../day-9/input.txt");
+
+const EXAMPLES: [&str; 3] = [
+    "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+    "1102,34915192,34915192,7,4,7,99,0",
+    "104,1125899906842624,99",
+];
+
+/// The decoder the VM used before `Instruction`, copied as it was so the comparison
+/// measures it rather than today's `parse_op`, which wraps `Instruction::decode`
+fn original_parse_op(address: usize, opcode: isize) -> Result<(usize, Vec<ArgMode>), IntcodeError> {
+    let op = opcode % 100;
+    let num_args = match op {
+        1 | 2 => 3,
+        3 | 4 | 9 => 1,
+        5 | 6 => 2,
+        7 | 8 => 3,
+        99 => 0,
+        _ => return Err(IntcodeError::UnknownOpcode { address, opcode }),
+    };
+    let mut remaining = opcode / 100;
+
+    let mut arg_modes = vec![0; num_args];
+    for arg_mode in arg_modes.iter_mut() {
+        *arg_mode = match remaining % 10 {
+            0 => MODE_POS,
+            1 => MODE_IMM,
+            2 => MODE_REL,
+            _ => return Err(IntcodeError::InvalidArgMode { address, opcode }),
+        };
+        remaining /= 10;
+    }
+
+    Ok((op as usize, arg_modes))
+}
+
+fn average<F: FnMut()>(runs: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    start.elapsed() / runs
+}
+
+fn run(program: &IntcodeProgram, input: isize, cached: bool) -> Vec<isize> {
+    let mut program = program.clone();
+    if cached {
+        program.enable_decode_cache();
+    }
+    program.push_input(input);
+    program.run_to_halt().expect("benchmark program failed")
+}
+
+fn main() {
+    // Decoding every opcode in the day 9 input, with the original decoder that returned
+    // a `Vec` of modes and into an `Instruction`
+    let opcodes: Vec<isize> = read_program(DAY_9_INPUT)
+        .memory()
+        .iter()
+        .copied()
+        .filter(|opcode| original_parse_op(0, *opcode).is_ok())
+        .collect();
+    let with_vec = average(1000, || {
+        for opcode in opcodes.iter() {
+            std::hint::black_box(original_parse_op(0, *opcode).unwrap());
+        }
+    });
+    let inline = average(1000, || {
+        for opcode in opcodes.iter() {
+            std::hint::black_box(Instruction::decode(0, *opcode).unwrap());
+        }
+    });
+    println!("decode {} opcodes", opcodes.len());
+    println!("    original parse_op   {:?}", with_vec);
+    println!("    Instruction::decode {:?}", inline);
+
+    let examples: Vec<IntcodeProgram> = EXAMPLES.iter().map(|ops| read_program(ops)).collect();
+    for cached in [false, true] {
+        let time = average(10_000, || {
+            for program in examples.iter() {
+                run(program, 0, cached);
+            }
+        });
+        println!("day 9 examples, cached: {:<5} {:?}", cached, time);
+    }
+
+    let boost = read_program(DAY_9_INPUT);
+    for cached in [false, true] {
+        let time = average(20, || {
+            run(&boost, 2, cached);
+        });
+        println!("day 9 part 2,   cached: {:<5} {:?}", cached, time);
+    }
+}
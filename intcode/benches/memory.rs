@@ -0,0 +1,64 @@
+//! Compares dense and paged memory. Run with `cargo bench --bench memory`.
+
+use intcode::{assemble, IntcodeProgram, MemoryKind};
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 20;
+
+/// Writes `count` values, `stride` cells apart, then outputs how many it wrote
+fn strided_writes(count: usize, stride: usize) -> Vec<isize> {
+    assemble(&format!(
+        "
+                arb #buf
+        fill:   add [i], #0, rel(0)
+                arb #{stride}
+                add [i], #1, [i]
+                lt [i], #{count}, [flag]
+                jnz [flag], #fill
+                out [i]
+                hlt
+        i:      .data 0
+        flag:   .data 0
+        buf:    .data 0
+        ",
+        count = count,
+        stride = stride
+    ))
+    .expect("benchmark program should assemble")
+}
+
+fn time(ops: &[isize], kind: MemoryKind) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let mut program = IntcodeProgram::with_memory(ops.to_vec(), kind);
+        program.run_to_halt().expect("benchmark program failed");
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let workloads = vec![
+        ("compact, 100000 cells", strided_writes(100_000, 1), true),
+        (
+            "spread out, stride 100000",
+            strided_writes(100, 100_000),
+            true,
+        ),
+        // Far too large to run densely
+        (
+            "huge, stride 10^9",
+            strided_writes(100, 1_000_000_000),
+            false,
+        ),
+    ];
+
+    for (name, ops, run_dense) in workloads {
+        for kind in [MemoryKind::Dense, MemoryKind::Paged] {
+            if kind == MemoryKind::Dense && !run_dense {
+                println!("{:<28} {:?}: skipped", name, kind);
+                continue;
+            }
+            println!("{:<28} {:?}: {:?} per run", name, kind, time(&ops, kind));
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{IntcodeError, IntcodeProgram, IntcodeResult};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+#[derive(Debug)]
+pub enum AsciiError {
+    Intcode(IntcodeError),
+    /// A line to send contained characters outside the ASCII range
+    NotAscii(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for AsciiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsciiError::Intcode(error) => write!(f, "{}", error),
+            AsciiError::NotAscii(line) => write!(f, "`{}` is not ASCII", line),
+            AsciiError::Io(error) => write!(f, "terminal I/O failed: {}", error),
+        }
+    }
+}
+
+impl Error for AsciiError {}
+
+impl From<IntcodeError> for AsciiError {
+    fn from(error: IntcodeError) -> Self {
+        AsciiError::Intcode(error)
+    }
+}
+
+impl From<io::Error> for AsciiError {
+    fn from(error: io::Error) -> Self {
+        AsciiError::Io(error)
+    }
+}
+
+/// The character codes for a typed line, ending with a newline. Any line ending already
+/// on it is replaced.
+pub(crate) fn encode_line(line: &str) -> Result<Vec<isize>, AsciiError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if !line.is_ascii() {
+        return Err(AsciiError::NotAscii(line.to_string()));
+    }
+
+    let mut codes: Vec<isize> = line.bytes().map(isize::from).collect();
+    codes.push(isize::from(b'\n'));
+    Ok(codes)
+}
+
+/// Appends an output to `text`, as a character if it's ASCII and otherwise as a number
+/// on its own line
+pub(crate) fn render(value: isize, text: &mut String) {
+    match value {
+        0..=127 => text.push(value as u8 as char),
+        _ => {
+            text.push_str(&value.to_string());
+            text.push('\n');
+        }
+    }
+}
+
+/// What a program printed before it stopped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AsciiOutput {
+    /// Every output, with values outside the ASCII range written as numbers
+    pub text: String,
+    /// Just the outputs outside the ASCII range, in order
+    pub values: Vec<isize>,
+}
+
+impl AsciiOutput {
+    pub fn from_values(values: &[isize]) -> Self {
+        let mut output = Self::default();
+        for value in values {
+            render(*value, &mut output.text);
+            if !(0..=127).contains(value) {
+                output.values.push(*value);
+            }
+        }
+        output
+    }
+}
+
+/// The record of a scripted run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The program's text with each line of the script echoed where it was sent, as
+    /// it would look on a terminal
+    pub transcript: String,
+    /// The outputs outside the ASCII range, in order
+    pub values: Vec<isize>,
+    /// Why the program stopped. `NeedsInput` means the script ran out.
+    pub result: IntcodeResult,
+}
+
+/// Drives a program that talks in ASCII, running it until it wants a line of input and
+/// turning its outputs into text.
+#[derive(Debug, Clone)]
+pub struct AsciiTerminal {
+    program: IntcodeProgram,
+}
+
+impl AsciiTerminal {
+    pub fn new(program: IntcodeProgram) -> Self {
+        Self { program }
+    }
+
+    pub fn program(&self) -> &IntcodeProgram {
+        &self.program
+    }
+
+    pub fn into_program(self) -> IntcodeProgram {
+        self.program
+    }
+
+    /// Queues a line for the program, followed by a newline
+    pub fn send_line(&mut self, line: &str) -> Result<(), AsciiError> {
+        self.program.extend_inputs(encode_line(line)?);
+        Ok(())
+    }
+
+    /// Runs until the program needs input, halts or runs out of budget, returning what
+    /// it printed on the way
+    pub fn run(&mut self) -> Result<(AsciiOutput, IntcodeResult), AsciiError> {
+        let (outputs, result) = self.program.run_until_input()?;
+        Ok((AsciiOutput::from_values(&outputs), result))
+    }
+
+    /// Sends each line of `script` whenever the program asks for input, until it halts
+    /// or the script runs out
+    pub fn run_script<'a, I: IntoIterator<Item = &'a str>>(
+        &mut self,
+        script: I,
+    ) -> Result<Session, AsciiError> {
+        let mut lines = script.into_iter();
+        let mut session = Session {
+            transcript: String::new(),
+            values: Vec::new(),
+            result: IntcodeResult::NeedsInput,
+        };
+
+        loop {
+            let (output, result) = self.run()?;
+            session.transcript.push_str(&output.text);
+            session.values.extend(output.values);
+            session.result = result;
+
+            if session.result != IntcodeResult::NeedsInput {
+                return Ok(session);
+            }
+            match lines.next() {
+                Some(line) => {
+                    self.send_line(line)?;
+                    session.transcript.push_str(line);
+                    session.transcript.push('\n');
+                }
+                None => return Ok(session),
+            }
+        }
+    }
+
+    /// Writes the program's text to `writer` and reads a line from `reader` each time it
+    /// asks for input, until it halts or the input ends
+    pub fn run_interactive<R: BufRead, W: Write>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+    ) -> Result<IntcodeResult, AsciiError> {
+        loop {
+            let (output, result) = self.run()?;
+            writer.write_all(output.text.as_bytes())?;
+            writer.flush()?;
+
+            if result != IntcodeResult::NeedsInput {
+                return Ok(result);
+            }
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(result);
+            }
+            self.send_line(&line)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+    use std::io::Cursor;
+
+    /// Prints `?`, then echoes a line back with a `!` and prints 1000
+    fn greeter() -> IntcodeProgram {
+        IntcodeProgram::new(
+            assemble(
+                "
+                        out #63
+                        out #10
+                loop:   in [c]
+                        eq [c], #10, [t]
+                        jnz [t], #done
+                        out [c]
+                        jz #0, #loop
+                done:   out #33
+                        out #10
+                        out #1000
+                        hlt
+                c:      .data 0
+                t:      .data 0
+                ",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn output_text() {
+        let output = AsciiOutput::from_values(&[104, 105, 10, 128, -1]);
+        assert_eq!(output.text, "hi\n128\n-1\n");
+        assert_eq!(output.values, vec![128, -1]);
+    }
+
+    #[test]
+    fn scripted() {
+        let mut terminal = AsciiTerminal::new(greeter());
+        let session = terminal.run_script(vec!["bob"]).unwrap();
+        assert_eq!(session.transcript, "?\nbob\nbob!\n1000\n");
+        assert_eq!(session.values, vec![1000]);
+        assert_eq!(session.result, IntcodeResult::Halt);
+
+        let mut terminal = AsciiTerminal::new(greeter());
+        let session = terminal.run_script(Vec::new()).unwrap();
+        assert_eq!(session.transcript, "?\n");
+        assert_eq!(session.result, IntcodeResult::NeedsInput);
+    }
+
+    #[test]
+    fn interactive() {
+        let mut terminal = AsciiTerminal::new(greeter());
+        let mut screen = Vec::new();
+        let result = terminal
+            .run_interactive(Cursor::new("alice\r\n"), &mut screen)
+            .unwrap();
+        assert_eq!(result, IntcodeResult::Halt);
+        assert_eq!(String::from_utf8(screen).unwrap(), "?\nalice!\n1000\n");
+    }
+
+    #[test]
+    fn rejects_non_ascii() {
+        let mut terminal = AsciiTerminal::new(greeter());
+        assert!(matches!(
+            terminal.send_line("héllo"),
+            Err(AsciiError::NotAscii(_))
+        ));
+        let (output, result) = terminal.run().unwrap();
+        assert_eq!(output.text, "?\n");
+        assert_eq!(result, IntcodeResult::NeedsInput);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::parse_op;
+use std::{collections::HashMap, error::Error, fmt};
+
+/// Mnemonics for each opcode, in the order given by the puzzle spec
+pub(crate) const MNEMONICS: [(usize, &str); 10] = [
+    (1, "add"),
+    (2, "mul"),
+    (3, "in"),
+    (4, "out"),
+    (5, "jnz"),
+    (6, "jz"),
+    (7, "lt"),
+    (8, "eq"),
+    (9, "arb"),
+    (99, "hlt"),
+];
+
+/// The index of the parameter an opcode writes its result to, if any
+pub(crate) fn write_param(op: usize) -> Option<usize> {
+    match op {
+        1 | 2 | 7 | 8 => Some(2),
+        3 => Some(0),
+        _ => None,
+    }
+}
+
+/// A problem found while assembling, pointing at the source line responsible
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub text: String,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}\n    {}", self.line, self.message, self.text)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A value that may refer to a label, e.g. `12`, `loop` or `buffer+3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expr {
+    label: Option<String>,
+    offset: isize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Instruction {
+        op: usize,
+        params: Vec<(isize, Expr)>,
+    },
+    Data(Vec<Expr>),
+}
+
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Item::Instruction { params, .. } => params.len() + 1,
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn parse_expr(token: &str) -> Result<Expr, String> {
+    let token = token.trim();
+    if let Ok(offset) = token.parse::<isize>() {
+        return Ok(Expr {
+            label: None,
+            offset,
+        });
+    }
+
+    // Split `label+3` / `label-3` on the operator following the label
+    let (label, offset) = match token.find(['+', '-']) {
+        Some(split) => {
+            let offset = token[split..]
+                .replace('+', "")
+                .trim()
+                .parse::<isize>()
+                .map_err(|_| format!("invalid offset in `{}`", token))?;
+            (token[..split].trim(), offset)
+        }
+        None => (token, 0),
+    };
+
+    if !is_identifier(label) {
+        return Err(format!("expected a number or label, found `{}`", token));
+    }
+
+    Ok(Expr {
+        label: Some(label.to_string()),
+        offset,
+    })
+}
+
+fn parse_param(token: &str) -> Result<(isize, Expr), String> {
+    let token = token.trim();
+    if let Some(inner) = token.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Ok((0, parse_expr(inner)?))
+    } else if let Some(inner) = token.strip_prefix('#') {
+        Ok((1, parse_expr(inner)?))
+    } else if let Some(inner) = token.strip_prefix("rel(").and_then(|t| t.strip_suffix(')')) {
+        Ok((2, parse_expr(inner)?))
+    } else {
+        Err(format!(
+            "parameter `{}` needs a mode: [address], #value or rel(offset)",
+            token
+        ))
+    }
+}
+
+fn parse_statement(statement: &str) -> Result<Item, String> {
+    let (head, rest) = match statement.find(char::is_whitespace) {
+        Some(split) => (&statement[..split], statement[split..].trim()),
+        None => (statement, ""),
+    };
+    let tokens: Vec<&str> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').collect()
+    };
+
+    if let Some(directive) = head.strip_prefix('.') {
+        return match directive {
+            "data" if !tokens.is_empty() => Ok(Item::Data(
+                tokens
+                    .into_iter()
+                    .map(parse_expr)
+                    .collect::<Result<_, _>>()?,
+            )),
+            "data" => Err("`.data` needs at least one value".to_string()),
+            _ => Err(format!("unknown directive `.{}`", directive)),
+        };
+    }
+
+    let op = MNEMONICS
+        .iter()
+        .find(|(_, mnemonic)| mnemonic.eq_ignore_ascii_case(head))
+        .map(|(op, _)| *op)
+        .ok_or_else(|| format!("unknown mnemonic `{}`", head))?;
+    let (_, arg_modes) = parse_op(0, op as isize).expect("mnemonic table has an unknown opcode");
+
+    if tokens.len() != arg_modes.len() {
+        return Err(format!(
+            "`{}` takes {} parameter(s) but {} were given",
+            head,
+            arg_modes.len(),
+            tokens.len()
+        ));
+    }
+
+    let params: Vec<(isize, Expr)> = tokens
+        .into_iter()
+        .map(parse_param)
+        .collect::<Result<_, _>>()?;
+
+    if let Some(idx) = write_param(op) {
+        if params[idx].0 == 1 {
+            return Err(format!(
+                "`{}` writes to its parameter {}, which cannot be immediate",
+                head,
+                idx + 1
+            ));
+        }
+    }
+
+    Ok(Item::Instruction { op, params })
+}
+
+/// Assembles Intcode source into a memory image for `IntcodeProgram::new`.
+///
+/// Each line holds an optional `label:`, then either an instruction such as
+/// `add [a], #5, rel(-1)` or a `.data` directive listing raw values. Parameters
+/// are written `[address]` for position mode, `#value` for immediate mode and
+/// `rel(offset)` for relative mode, and anything after a `;` is a comment.
+pub fn assemble(source: &str) -> Result<Vec<isize>, AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut items: Vec<(usize, &str, Item)> = Vec::new();
+    let mut address = 0;
+
+    for (idx, raw_line) in source.lines().enumerate() {
+        let error = |message: String| AsmError {
+            line: idx + 1,
+            text: raw_line.trim().to_string(),
+            message,
+        };
+
+        let mut statement = raw_line.split(';').next().unwrap_or("").trim();
+
+        // Peel off any labels at the start of the line
+        while let Some(split) = statement.find(':') {
+            let label = statement[..split].trim();
+            if !is_identifier(label) {
+                return Err(error(format!("invalid label `{}`", label)));
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(error(format!(
+                    "label `{}` is defined more than once",
+                    label
+                )));
+            }
+            statement = statement[split + 1..].trim();
+        }
+
+        if statement.is_empty() {
+            continue;
+        }
+
+        let item = parse_statement(statement).map_err(error)?;
+        address += item.len();
+        items.push((idx + 1, raw_line.trim(), item));
+    }
+
+    let mut image = Vec::with_capacity(address);
+    for (line, text, item) in items {
+        let error = |message: String| AsmError {
+            line,
+            text: text.to_string(),
+            message,
+        };
+        let resolve = |expr: &Expr| match &expr.label {
+            Some(label) => {
+                let address = labels
+                    .get(label)
+                    .ok_or_else(|| error(format!("undefined label `{}`", label)))?;
+                (*address as isize)
+                    .checked_add(expr.offset)
+                    .ok_or_else(|| error(format!("offset from `{}` is out of range", label)))
+            }
+            None => Ok(expr.offset),
+        };
+
+        match item {
+            Item::Instruction { op, params } => {
+                let opcode = params
+                    .iter()
+                    .enumerate()
+                    .fold(op as isize, |opcode, (idx, (mode, _))| {
+                        opcode + mode * 10_isize.pow(idx as u32 + 2)
+                    });
+                image.push(opcode);
+                for (_, expr) in params.iter() {
+                    image.push(resolve(expr)?);
+                }
+            }
+            Item::Data(values) => {
+                for expr in values.iter() {
+                    image.push(resolve(expr)?);
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn amp_example() {
+        let source = "
+                in [a]
+                in [b]
+                mul [b], #10, [b]
+                add [b], [a], [a]
+                out [a]
+                hlt
+            a:  .data 0
+            b:  .data 0
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0
+            ])
+        );
+    }
+
+    #[test]
+    fn feedback_example() {
+        let source = "
+                    in [phase]
+                    add [phase], #-4, [phase]
+            loop:   in [signal]             ; read the next signal
+                    mul [signal], #2, [signal]
+                    add [signal], [phase], [signal]
+                    out [signal]
+                    add [count], #-1, [count]
+                    jnz [count], #loop
+                    hlt
+            phase:  .data 0
+            signal: .data 0
+            count:  .data 5
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28,
+                -1, 28, 1005, 28, 6, 99, 0, 0, 5
+            ])
+        );
+    }
+
+    #[test]
+    fn relative_and_offsets() {
+        let source = "
+            arb #buf+1
+            out rel(-1)
+            out [buf+1]
+            hlt
+            buf: .data 7, 8, buf
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![109, 8, 204, -1, 4, 8, 99, 7, 8, 7])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = assemble("hlt\nfoo [a]").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.text, "foo [a]");
+        assert_eq!(error.message, "unknown mnemonic `foo`");
+
+        let error = assemble("out [nowhere]").unwrap_err();
+        assert_eq!(error.message, "undefined label `nowhere`");
+
+        let error = assemble("add #1, #2, #3").unwrap_err();
+        assert_eq!(
+            error.message,
+            "`add` writes to its parameter 3, which cannot be immediate"
+        );
+
+        let error = assemble("x: hlt\nx: hlt").unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "label `x` is defined more than once")
+        );
+
+        let error = assemble("out 5").unwrap_err();
+        assert_eq!(
+            error.message,
+            "parameter `5` needs a mode: [address], #value or rel(offset)"
+        );
+
+        let error = assemble("hlt\nx: out [x+9223372036854775807]").unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "offset from `x` is out of range")
+        );
+
+        let error = assemble("jnz #1").unwrap_err();
+        assert_eq!(error.message, "`jnz` takes 2 parameter(s) but 1 were given");
+    }
+}
@@ -0,0 +1,131 @@
+//! The machines days 2, 5, 7 and 9 ran before they moved to the shared
+//! `IntcodeProgram`, kept as oracles for the differential fuzzer.
+//!
+//! Each is copied as it was apart from its printing and its `run` loop, so that none of
+//! them can pick up a bug from the shared code. They never reported errors: given
+//! something the puzzles left undefined, they panic, write somewhere else, or grow
+//! memory their own way. The adapters here give up on those cases rather than guess
+//! what the shared machine should have done, and leave them to the other
+//! implementations.
+//!
+//! Arithmetic overflow panics in debug builds and wraps in release builds, so the
+//! baselines stand for `OverflowPolicy::Wrap`.
+
+// The copied code is left the way it was written
+#![allow(clippy::needless_range_loop)]
+
+mod day2;
+mod day5;
+mod day7;
+mod day9;
+
+use crate::fuzz::{Ending, Outcome};
+use std::panic::{self, AssertUnwindSafe};
+
+pub(crate) use day2::run as run_day2;
+pub(crate) use day5::run as run_day5;
+pub(crate) use day7::run as run_day7;
+pub(crate) use day9::run as run_day9;
+
+/// What the adapter needs from the day 5, 7 and 9 machines
+trait Baseline {
+    /// The highest parameter mode the machine knows
+    const MAX_MODE: isize;
+
+    fn memory(&self) -> &[isize];
+
+    fn exec_ptr(&self) -> usize;
+
+    fn relative_base(&self) -> isize {
+        0
+    }
+
+    /// Sets the value the next input instruction reads
+    fn give_input(&mut self, value: isize);
+
+    fn step(&mut self);
+
+    /// The value output by the last step, if any
+    fn take_output(&mut self) -> Option<isize>;
+}
+
+/// Runs `f`, or gives up on the case if it panics
+fn guarded<T, F: FnOnce() -> T>(f: F) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
+
+/// The non-zero cells of `memory`, by address
+fn cells<I: Iterator<Item = isize>>(memory: I) -> impl Iterator<Item = (usize, isize)> {
+    memory.enumerate().filter(|(_, value)| *value != 0)
+}
+
+/// Whether the baselines run the instruction at `ptr` the way the puzzles describe: it
+/// only uses modes up to `max_mode`, doesn't write to an immediate parameter, and only
+/// touches addresses already in memory. Unknown opcodes are left to panic.
+fn is_defined(ops: &[isize], ptr: usize, relative_base: isize, max_mode: isize) -> bool {
+    let opcode = ops[ptr];
+    let (arity, writes_last) = match opcode % 100 {
+        1 | 2 | 7 | 8 => (3, true),
+        3 => (1, true),
+        4 | 9 => (1, false),
+        5 | 6 => (2, false),
+        _ => return true,
+    };
+
+    (0..arity).all(|index| {
+        let param = ptr + 1 + index;
+        let mode = opcode / 10_isize.pow(index as u32 + 2) % 10;
+        if param >= ops.len() || mode > max_mode {
+            return false;
+        }
+        let target = match mode {
+            1 => return !(writes_last && index == arity - 1),
+            2 => ops[param].checked_add(relative_base),
+            _ => Some(ops[param]),
+        };
+        matches!(target, Some(target) if target >= 0 && (target as usize) < ops.len())
+    })
+}
+
+/// Feeds `inputs` to a machine one instruction at a time, counting steps the same way
+/// as the shared machine
+fn run_machine<M: Baseline>(mut machine: M, inputs: &[isize], steps: u64) -> Option<Outcome> {
+    let mut inputs = inputs.iter().copied();
+    let mut outputs = Vec::new();
+    let mut executed = 0;
+
+    let ending = loop {
+        if executed == steps {
+            break Ending::OutOfSteps;
+        }
+        let ops = machine.memory();
+        let ptr = machine.exec_ptr();
+        if ptr >= ops.len() || !is_defined(ops, ptr, machine.relative_base(), M::MAX_MODE) {
+            return None;
+        }
+
+        let opcode = ops[ptr] % 100;
+        if opcode == 3 {
+            match inputs.next() {
+                Some(value) => machine.give_input(value),
+                None => break Ending::NeedsInput,
+            }
+        }
+        guarded(|| machine.step())?;
+        executed += 1;
+        outputs.extend(machine.take_output());
+
+        if opcode == 99 {
+            break Ending::Halted;
+        }
+    };
+
+    let memory = machine.memory();
+    Some(Outcome::new(
+        outputs,
+        ending,
+        memory.len(),
+        cells(memory.iter().copied()),
+        Vec::new(),
+    ))
+}
@@ -0,0 +1,79 @@
+use super::{cells, guarded};
+use crate::fuzz::{Ending, Outcome};
+use crate::OverflowPolicy;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IntcodeProgram {
+    data: Vec<usize>,
+}
+
+impl IntcodeProgram {
+    pub fn new(input: Vec<usize>) -> Self {
+        Self { data: input }
+    }
+
+    pub fn run_instruction(self, index: usize) -> Self {
+        let ptr = index * 4;
+        let instruction = if self.data.len() - ptr >= 4 {
+            self.data[ptr..ptr + 4].to_vec()
+        } else {
+            vec![99, 0, 0, 0]
+        };
+
+        let mut data = self.data;
+        // Apply the op codes to the result, which we keep mutable
+        match instruction.as_slice() {
+            [1, a, b, dest] => {
+                data[*dest] = data[*a] + data[*b];
+            }
+            [2, a, b, dest] => {
+                data[*dest] = data[*a] * data[*b];
+            }
+            [99, _, _, _] => {
+                // println!("Program finished");
+            }
+            _ => panic!("Unexpected op code {}", data[ptr]),
+        };
+
+        Self::new(data)
+    }
+}
+
+/// Steps through the program four cells at a time, as day 2 did. It has no `hlt` of its
+/// own, running off the end instead, so it stops at the first one.
+pub(crate) fn run(
+    ops: &[isize],
+    _inputs: &[isize],
+    steps: u64,
+    _policy: OverflowPolicy,
+) -> Option<Outcome> {
+    let mut program = IntcodeProgram::new(ops.iter().map(|value| *value as usize).collect());
+    let mut index = 0;
+    let mut executed = 0;
+
+    let ending = loop {
+        if executed == steps {
+            break Ending::OutOfSteps;
+        }
+        // Short of a whole instruction, day 2 carries on as if it were a `hlt`
+        let ptr = index * 4;
+        match program.data.get(ptr) {
+            Some(99) => break Ending::Halted,
+            Some(_) if program.data.len() - ptr >= 4 => {}
+            _ => return None,
+        }
+
+        program = guarded(move || program.run_instruction(index))?;
+        index += 1;
+        executed += 1;
+    };
+
+    let memory = program.data.iter().map(|value| *value as isize);
+    Some(Outcome::new(
+        Vec::new(),
+        ending,
+        program.data.len(),
+        cells(memory),
+        Vec::new(),
+    ))
+}
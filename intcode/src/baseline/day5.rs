@@ -0,0 +1,206 @@
+use super::{run_machine, Baseline};
+use crate::fuzz::Outcome;
+use crate::OverflowPolicy;
+
+pub fn parse_op(opcode: isize) -> (usize, Vec<ArgMode>) {
+    let op = (opcode % 100) as usize;
+    let num_args = match op {
+        1 | 2 => 3,
+        3 | 4 => 1,
+        5 | 6 => 2,
+        7 | 8 => 3,
+        99 => 0,
+        _ => unreachable!("Unknown opcode {}", opcode),
+    };
+    let mut remaining = opcode / 100;
+
+    let mut arg_modes = vec![0; num_args];
+    for i in 0..num_args {
+        arg_modes[i] = (remaining % 10) as ArgMode;
+        remaining /= 10;
+    }
+
+    (op, arg_modes)
+}
+
+type ArgMode = u8;
+
+const MODE_POS: ArgMode = 0;
+const MODE_IMM: ArgMode = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IntcodeProgram {
+    ops: Vec<isize>,
+    exec_ptr: usize,
+
+    input: isize,
+    output: Vec<isize>,
+}
+
+impl IntcodeProgram {
+    pub fn new(ops: Vec<isize>, input: isize) -> Self {
+        Self {
+            ops,
+            exec_ptr: 0,
+
+            input,
+            output: Vec::new(),
+        }
+    }
+
+    fn get_arg(&self, ptr: usize, mode: ArgMode) -> isize {
+        if mode == MODE_POS {
+            let arg_value = self.ops[ptr] as usize;
+            self.ops[arg_value]
+        } else if mode == MODE_IMM {
+            self.ops[ptr]
+        } else {
+            unreachable!("invalid arg mode {}", mode);
+        }
+    }
+
+    fn op_add(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let a = self.get_arg(ptr, arg_modes[0]);
+        let b = self.get_arg(ptr + 1, arg_modes[1]);
+
+        // arg 3 is always positional, and works a bit differently since
+        // we store the value instead of reading it
+        let dest = self.ops[ptr + 2] as usize;
+        self.ops[dest] = a + b;
+        self.exec_ptr += 3;
+    }
+
+    fn op_mult(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let a = self.get_arg(ptr, arg_modes[0]);
+        let b = self.get_arg(ptr + 1, arg_modes[1]);
+
+        // arg 3 is always positional, and works a bit differently since
+        // we store the value instead of reading it
+        let dest = self.ops[ptr + 2] as usize;
+        self.ops[dest] = a * b;
+        self.exec_ptr += 3;
+    }
+
+    fn op_input(&mut self, _arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let dest = self.ops[ptr] as usize;
+
+        self.ops[dest] = self.input;
+
+        self.exec_ptr += 1;
+    }
+
+    fn op_output(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let output_value = self.get_arg(ptr, arg_modes[0]);
+
+        self.output.push(output_value);
+        self.exec_ptr += 1;
+    }
+
+    fn op_jump_if_true(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let condition = self.get_arg(ptr, arg_modes[0]) != 0;
+
+        if condition {
+            // Jump to the designated location
+            self.exec_ptr = self.get_arg(ptr + 1, arg_modes[1]) as usize;
+        } else {
+            // Move on to the next op
+            self.exec_ptr += 2;
+        }
+    }
+
+    fn op_jump_if_false(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let condition = self.get_arg(ptr, arg_modes[0]) == 0;
+
+        if condition {
+            // Jump to the designated location
+            self.exec_ptr = self.get_arg(ptr + 1, arg_modes[1]) as usize;
+        } else {
+            // Move on to the next op
+            self.exec_ptr += 2;
+        }
+    }
+
+    fn op_less_than(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let a = self.get_arg(ptr, arg_modes[0]);
+        let b = self.get_arg(ptr + 1, arg_modes[1]);
+
+        let result = if a < b { 1 } else { 0 };
+        let dest = self.ops[ptr + 2] as usize;
+        self.ops[dest] = result;
+
+        self.exec_ptr += 3;
+    }
+
+    fn op_equals(&mut self, arg_modes: Vec<ArgMode>) {
+        let ptr = self.exec_ptr;
+        let a = self.get_arg(ptr, arg_modes[0]);
+        let b = self.get_arg(ptr + 1, arg_modes[1]);
+
+        let result = if a == b { 1 } else { 0 };
+        let dest = self.ops[ptr + 2] as usize;
+        self.ops[dest] = result;
+
+        self.exec_ptr += 3;
+    }
+
+    fn run_instruction(&mut self) {
+        let (opcode, arg_modes) = parse_op(self.ops[self.exec_ptr]);
+        self.exec_ptr += 1;
+
+        match opcode {
+            1 => self.op_add(arg_modes),
+            2 => self.op_mult(arg_modes),
+            3 => self.op_input(arg_modes),
+            4 => self.op_output(arg_modes),
+            5 => self.op_jump_if_true(arg_modes),
+            6 => self.op_jump_if_false(arg_modes),
+            7 => self.op_less_than(arg_modes),
+            8 => self.op_equals(arg_modes),
+            99 => {
+                self.exec_ptr = self.ops.len();
+            }
+            _ => unreachable!("Unrecognized opcode {}", opcode),
+        };
+    }
+}
+
+/// Day 5 was given a single input value, which every input instruction read
+impl Baseline for IntcodeProgram {
+    const MAX_MODE: isize = MODE_IMM as isize;
+
+    fn memory(&self) -> &[isize] {
+        &self.ops
+    }
+
+    fn exec_ptr(&self) -> usize {
+        self.exec_ptr
+    }
+
+    fn give_input(&mut self, value: isize) {
+        self.input = value;
+    }
+
+    fn step(&mut self) {
+        self.run_instruction();
+    }
+
+    fn take_output(&mut self) -> Option<isize> {
+        self.output.pop()
+    }
+}
+
+pub(crate) fn run(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    _policy: OverflowPolicy,
+) -> Option<Outcome> {
+    run_machine(IntcodeProgram::new(ops.to_vec(), 0), inputs, steps)
+}
@@ -0,0 +1,319 @@
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt,
+    ops::{Add, Mul},
+};
+
+/// A signed integer of any size, for the cells that `OverflowPolicy::Widen` and
+/// `OverflowPolicy::Arbitrary` let grow past `isize`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// Little endian base 2^32 digits with no trailing zeros, so zero is empty
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub(crate) fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        Self {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+
+    /// The sign and the base 2^32 digits of the magnitude, least significant first
+    pub(crate) fn parts(&self) -> (bool, &[u32]) {
+        (self.negative, &self.magnitude)
+    }
+
+    /// Parses an optionally negative decimal number
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return None;
+        }
+
+        let ten = BigInt::from(10_isize);
+        let value = digits.bytes().fold(BigInt::default(), |value, digit| {
+            &(&value * &ten) + &BigInt::from((digit - b'0') as isize)
+        });
+        Some(Self::from_parts(negative, value.magnitude))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    fn low_bits(&self) -> u128 {
+        self.magnitude
+            .iter()
+            .take(4)
+            .rev()
+            .fold(0, |bits, digit| bits << 32 | *digit as u128)
+    }
+
+    pub fn to_i128(&self) -> Option<i128> {
+        if self.magnitude.len() > 4 {
+            return None;
+        }
+        let magnitude = self.low_bits();
+        if !self.negative {
+            i128::try_from(magnitude).ok()
+        } else if magnitude <= i128::MIN.unsigned_abs() {
+            Some((magnitude as i128).wrapping_neg())
+        } else {
+            None
+        }
+    }
+
+    pub fn to_isize(&self) -> Option<isize> {
+        self.to_i128().and_then(|value| isize::try_from(value).ok())
+    }
+
+    /// The low bits in two's complement, which is what `OverflowPolicy::Wrap` would
+    /// have given
+    pub fn wrapped(&self) -> isize {
+        let bits = self.low_bits();
+        if self.negative {
+            bits.wrapping_neg() as isize
+        } else {
+            bits as isize
+        }
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(value: i128) -> Self {
+        let mut rest = value.unsigned_abs();
+        let mut magnitude = Vec::new();
+        while rest > 0 {
+            magnitude.push(rest as u32);
+            rest >>= 32;
+        }
+        Self::from_parts(value < 0, magnitude)
+    }
+}
+
+impl From<isize> for BigInt {
+    fn from(value: isize) -> Self {
+        Self::from(value as i128)
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0;
+    for (i, digit) in long.iter().enumerate() {
+        let total = *digit as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+/// `a - b`, where `a` is at least as big as `b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, digit) in a.iter().enumerate() {
+        let mut total = *digit as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = 0;
+        if total < 0 {
+            total += 1 << 32;
+            borrow = 1;
+        }
+        difference.push(total as u32);
+    }
+    difference
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, y) in b.iter().enumerate() {
+            let total = *x as u64 * *y as u64 + product[i + j] as u64 + carry;
+            product[i + j] = total as u32;
+            carry = total >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+    product
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+        match cmp_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::from_parts(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::from_parts(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != other.negative,
+            mul_magnitude(&self.magnitude, &other.magnitude),
+        )
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peel off nine decimal digits at a time, least significant first
+        let mut rest = self.magnitude.clone();
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let mut remainder = 0;
+            for digit in rest.iter_mut().rev() {
+                let value = remainder << 32 | *digit as u64;
+                *digit = (value / 1_000_000_000) as u32;
+                remainder = value % 1_000_000_000;
+            }
+            chunks.push(remainder);
+            while rest.last() == Some(&0) {
+                rest.pop();
+            }
+        }
+
+        let digits = match chunks.split_last() {
+            Some((first, others)) => others
+                .iter()
+                .rev()
+                .fold(first.to_string(), |digits, chunk| {
+                    format!("{}{:09}", digits, chunk)
+                }),
+            None => "0".to_string(),
+        };
+        f.pad_integral(!self.negative, "", &digits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn big(value: i128) -> BigInt {
+        BigInt::from(value)
+    }
+
+    #[test]
+    fn conversions() {
+        for value in [0, 1, -1, i128::MAX, i128::MIN, u32::MAX as i128 + 1] {
+            assert_eq!(big(value).to_i128(), Some(value));
+        }
+        assert_eq!(big(isize::MIN as i128).to_isize(), Some(isize::MIN));
+        assert_eq!(big(isize::MAX as i128 + 1).to_isize(), None);
+        assert_eq!(big(isize::MAX as i128 + 1).wrapped(), isize::MIN);
+        assert_eq!(big(-(isize::MAX as i128) - 2).wrapped(), isize::MAX);
+
+        let past_i128 = &big(i128::MAX) + &big(1);
+        assert_eq!(past_i128.to_i128(), None);
+        assert_eq!((&big(i128::MIN) + &big(-1)).to_i128(), None);
+        assert!(big(0).is_zero() && !big(0).is_negative());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let values = [
+            0,
+            7,
+            -7,
+            1 << 40,
+            -(1 << 40),
+            i64::MAX as i128,
+            i64::MIN as i128,
+        ];
+        for a in values.iter() {
+            for b in values.iter() {
+                assert_eq!((&big(*a) + &big(*b)).to_i128(), Some(a + b));
+                assert_eq!((&big(*a) * &big(*b)).to_i128(), Some(a * b));
+                assert_eq!(big(*a).cmp(&big(*b)), a.cmp(b));
+            }
+        }
+
+        let huge = &big(1 << 100) * &big(1 << 100);
+        assert_eq!(
+            huge.to_string(),
+            "1606938044258990275541962092341162602522202993782792835301376"
+        );
+        assert!(huge > big(i128::MAX));
+        assert!((&huge * &big(-1)) < big(i128::MIN));
+        assert!((&huge + &(&huge * &big(-1))).is_zero());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(big(0).to_string(), "0");
+        assert_eq!(big(-1_000_000_000).to_string(), "-1000000000");
+        assert_eq!(big(i128::MIN).to_string(), i128::MIN.to_string());
+        assert_eq!(format!("{:>6}", big(-42)), "   -42");
+    }
+
+    #[test]
+    fn parsing() {
+        let huge = (&big(1 << 100) * &big(-(1 << 100))).to_string();
+        assert_eq!(
+            BigInt::parse(&huge).map(|value| value.to_string()),
+            Some(huge)
+        );
+        assert_eq!(BigInt::parse("-0"), Some(big(0)));
+        assert_eq!(BigInt::parse("007"), Some(big(7)));
+        for bad in ["", "-", "1-2", "+3", " 4"] {
+            assert_eq!(BigInt::parse(bad), None);
+        }
+    }
+}
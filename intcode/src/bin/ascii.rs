@@ -0,0 +1,32 @@
+use intcode::{read_program, AsciiTerminal, IntcodeResult};
+use std::{env, fs, io};
+
+const USAGE: &str = "Usage: ascii <path to program> [path to script]";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = args.next().expect(USAGE);
+    let program_str = fs::read_to_string(&path).expect("Could not find file.");
+    let mut terminal = AsciiTerminal::new(read_program(&program_str));
+
+    let result = match args.next() {
+        Some(script_path) => {
+            let script = fs::read_to_string(&script_path).expect("Could not find script.");
+            let session = terminal
+                .run_script(script.lines())
+                .expect("Intcode program failed");
+            print!("{}", session.transcript);
+            session.result
+        }
+        None => {
+            let stdin = io::stdin();
+            terminal
+                .run_interactive(stdin.lock(), io::stdout())
+                .expect("Intcode program failed")
+        }
+    };
+
+    if result != IntcodeResult::Halt {
+        eprintln!("Stopped before halting: {:?}", result);
+    }
+}
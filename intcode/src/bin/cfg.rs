@@ -0,0 +1,12 @@
+use intcode::{read_program, ControlFlowGraph};
+use std::{env, fs};
+
+const USAGE: &str = "Usage: cfg <path to program>";
+
+fn main() {
+    let path = env::args().nth(1).expect(USAGE);
+    let program_str = fs::read_to_string(&path).expect("Could not find file.");
+    let ops = read_program(&program_str).memory().into_owned();
+
+    print!("{}", ControlFlowGraph::build(&ops).to_dot(&ops));
+}
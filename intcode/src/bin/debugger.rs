@@ -0,0 +1,15 @@
+use intcode::{read_program, Debugger};
+use std::{env, fs, io};
+
+fn main() {
+    let path = env::args()
+        .nth(1)
+        .expect("Usage: debugger <path to program>");
+    let program_str = fs::read_to_string(&path).expect("Could not find file.");
+
+    let mut debugger = Debugger::new(read_program(&program_str));
+    let stdin = io::stdin();
+    debugger
+        .run_session(stdin.lock(), io::stdout())
+        .expect("Could not run debugger session");
+}
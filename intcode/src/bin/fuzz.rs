@@ -0,0 +1,40 @@
+use intcode::{fuzz, implementations, OpcodeSet, FUZZ_POLICIES};
+use std::{env, panic, process};
+
+const USAGE: &str = "Usage: fuzz [number of runs per opcode set] [first seed]";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let runs = args.next().map_or(10_000, |arg| arg.parse().expect(USAGE));
+    let seed = args.next().map_or(0, |arg| arg.parse().expect(USAGE));
+
+    // The baseline machines panic on cases they can't run, which only drops them from
+    // the comparison
+    let report = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let in_baseline = info
+            .location()
+            .is_some_and(|location| location.file().contains("baseline"));
+        if !in_baseline {
+            report(info);
+        }
+    }));
+
+    let mut diverged = false;
+    for policy in FUZZ_POLICIES.iter() {
+        for set in OpcodeSet::ALL.iter() {
+            match fuzz(&implementations(*set, *policy), *set, seed, runs) {
+                Some(divergence) => {
+                    println!("{:?}, {:?}: implementations disagree", set, policy);
+                    print!("{}", divergence);
+                    diverged = true;
+                }
+                None => println!("{:?}, {:?}: {} cases agree", set, policy, runs),
+            }
+        }
+    }
+
+    if diverged {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,31 @@
+use intcode::read_program;
+use std::{env, fs};
+
+const USAGE: &str = "Usage: profile <path to program> [--json] [input values...]";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = args.next().expect(USAGE);
+    let program_str = fs::read_to_string(&path).expect("Could not find file.");
+
+    let mut json = false;
+    let mut program = read_program(&program_str);
+    for arg in args {
+        if arg == "--json" {
+            json = true;
+        } else {
+            program.push_input(arg.parse().expect(USAGE));
+        }
+    }
+
+    program.enable_profiler();
+    let outputs = program.run_to_halt().expect("Intcode program failed");
+    let profile = program.take_profile().expect("profiler was enabled");
+
+    if json {
+        println!("{}", profile.to_json());
+    } else {
+        println!("outputs: {:?}", outputs);
+        print!("{}", profile.report());
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Instant;
+
+/// How many instructions run between checks of the deadline, since reading the clock
+/// costs more than most instructions
+pub const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// Counts the instructions a program executes and decides when it has to stop, and how
+/// far its memory may grow.
+///
+/// This is bookkeeping rather than machine state, so it is ignored when comparing
+/// programs.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Budget {
+    pub(crate) cycles: u64,
+    /// Instructions left to run, if limited
+    pub(crate) steps: Option<u64>,
+    pub(crate) deadline: Option<Instant>,
+    /// How many cells memory may grow to, if limited
+    pub(crate) memory: Option<usize>,
+}
+
+impl Budget {
+    /// Whether the program has to stop before running another instruction
+    pub(crate) fn is_exhausted(&self) -> bool {
+        if self.steps == Some(0) {
+            return true;
+        }
+
+        match self.deadline {
+            Some(deadline) if self.cycles.is_multiple_of(DEADLINE_CHECK_INTERVAL) => {
+                Instant::now() >= deadline
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn count_step(&mut self) {
+        self.cycles += 1;
+        if let Some(steps) = self.steps.as_mut() {
+            *steps -= 1;
+        }
+    }
+
+    /// Takes back a step that has been undone. Its budget is not refunded.
+    pub(crate) fn uncount_step(&mut self) {
+        self.cycles -= 1;
+    }
+}
+
+impl PartialEq for Budget {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Budget {}
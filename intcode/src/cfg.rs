@@ -0,0 +1,262 @@
+use crate::{
+    disasm::{decode, find_code, format_instruction, successors},
+    MODE_IMM,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How control passes from one block to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution carries on to the following instruction
+    FallThrough,
+    /// A jump to an immediate target is taken
+    Jump,
+}
+
+/// A straight run of instructions that is only entered at the top and only left at
+/// the bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// One past the last cell of the final instruction
+    pub end: usize,
+    /// The address of each instruction, in order
+    pub instructions: Vec<usize>,
+    /// The start of each block control can pass to
+    pub successors: Vec<(usize, EdgeKind)>,
+    /// Ends in a jump whose target is read from memory, so it may also go somewhere
+    /// that isn't listed in `successors`
+    pub indirect: bool,
+    pub halts: bool,
+}
+
+/// The basic blocks of the code reachable from address 0, keyed by start address.
+///
+/// Code is found the same way as for `disassemble`, by following fall-through and
+/// immediate jumps, so anything only reachable through an indirect jump is left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl ControlFlowGraph {
+    pub fn build(ops: &[isize]) -> Self {
+        let code = find_code(ops);
+
+        // A block starts at the entry point, at every jump target and after every jump
+        let mut leaders = BTreeSet::new();
+        if code.contains(&0) {
+            leaders.insert(0);
+        }
+        for address in code.iter() {
+            let decoded = decode(ops, *address).expect("code addresses should decode");
+            if decoded.op == 5 || decoded.op == 6 {
+                leaders.insert(address + decoded.len());
+                for (target, _) in successors(ops, *address, &decoded) {
+                    leaders.insert(target);
+                }
+            }
+        }
+        leaders.retain(|address| code.contains(address));
+
+        let mut blocks = BTreeMap::new();
+        for start in leaders.iter() {
+            let mut block = BasicBlock {
+                start: *start,
+                end: *start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                indirect: false,
+                halts: false,
+            };
+
+            let mut address = *start;
+            loop {
+                let decoded = decode(ops, address).expect("code addresses should decode");
+                let next = address + decoded.len();
+                block.instructions.push(address);
+                block.end = next;
+
+                if decoded.op == 5 || decoded.op == 6 || decoded.op == 99 {
+                    block.successors = successors(ops, address, &decoded)
+                        .into_iter()
+                        .filter(|(target, _)| code.contains(target))
+                        .collect();
+                    block.halts = decoded.op == 99;
+
+                    if decoded.op != 99 && decoded.arg_modes[1] != MODE_IMM {
+                        let condition = ops[address + 1];
+                        let never_jumps = decoded.arg_modes[0] == MODE_IMM
+                            && (decoded.op == 5) == (condition == 0);
+                        block.indirect = !never_jumps;
+                    }
+                    break;
+                }
+
+                if !code.contains(&next) {
+                    // Runs into something that doesn't decode, which will fail at runtime
+                    break;
+                }
+                if leaders.contains(&next) {
+                    block.successors = vec![(next, EdgeKind::FallThrough)];
+                    break;
+                }
+                address = next;
+            }
+
+            blocks.insert(*start, block);
+        }
+
+        Self { blocks }
+    }
+
+    /// The block holding the instruction that starts at, or covers, `address`
+    pub fn block_containing(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address < block.end)
+    }
+
+    /// Renders the graph in Graphviz DOT format, listing the instructions of each block.
+    /// Blocks that halt are drawn with a double border, and indirect jumps lead to a
+    /// `?` node.
+    pub fn to_dot(&self, ops: &[isize]) -> String {
+        let mut lines = vec![
+            "digraph cfg {".to_string(),
+            "    node [shape=box, fontname=\"monospace\"];".to_string(),
+        ];
+
+        for block in self.blocks.values() {
+            let label: String = block
+                .instructions
+                .iter()
+                .map(|address| {
+                    let decoded = decode(ops, *address).expect("code addresses should decode");
+                    let text = format_instruction(ops, *address, &decoded, |_| None);
+                    format!("{}: {}\\l", address, escape(&text))
+                })
+                .collect();
+            let style = if block.halts { ", peripheries=2" } else { "" };
+            lines.push(format!(
+                "    b{} [label=\"{}\"{}];",
+                block.start, label, style
+            ));
+
+            for (target, kind) in block.successors.iter() {
+                match kind {
+                    EdgeKind::FallThrough => {
+                        lines.push(format!("    b{} -> b{};", block.start, target))
+                    }
+                    EdgeKind::Jump => lines.push(format!(
+                        "    b{} -> b{} [label=\"jump\"];",
+                        block.start, target
+                    )),
+                }
+            }
+            if block.indirect {
+                lines.push(format!(
+                    "    indirect{} [label=\"?\", shape=circle];",
+                    block.start
+                ));
+                lines.push(format!(
+                    "    b{} -> indirect{} [label=\"jump\", style=dashed];",
+                    block.start, block.start
+                ));
+            }
+        }
+
+        lines.push("}".to_string());
+        let mut dot = lines.join("\n");
+        dot.push('\n');
+        dot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn loop_blocks() {
+        let ops = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let cfg = ControlFlowGraph::build(&ops);
+        let starts: Vec<&usize> = cfg.blocks.keys().collect();
+        assert_eq!(starts, vec![&0, &6, &25]);
+
+        assert_eq!(cfg.blocks[&0].instructions, vec![0, 2]);
+        assert_eq!(cfg.blocks[&0].successors, vec![(6, EdgeKind::FallThrough)]);
+        assert_eq!(
+            cfg.blocks[&6].successors,
+            vec![(25, EdgeKind::FallThrough), (6, EdgeKind::Jump)]
+        );
+        assert_eq!(cfg.blocks[&6].end, 25);
+        assert!(cfg.blocks[&25].halts);
+        assert!(cfg.blocks[&25].successors.is_empty());
+
+        assert_eq!(cfg.block_containing(13).map(|block| block.start), Some(6));
+        assert_eq!(cfg.block_containing(25).map(|block| block.start), Some(25));
+        assert_eq!(cfg.block_containing(26), None);
+    }
+
+    #[test]
+    fn indirect_and_unconditional_jumps() {
+        let ops = assemble(
+            "
+                    in [x]
+                    jz [x], [target]
+                    jnz #1, #end
+                    out [x]
+            end:    hlt
+            x:      .data 0
+            target: .data 0
+            ",
+        )
+        .unwrap();
+        let cfg = ControlFlowGraph::build(&ops);
+
+        // The `out` after the unconditional jump is never reached
+        let starts: Vec<&usize> = cfg.blocks.keys().collect();
+        assert_eq!(starts, vec![&0, &5, &10]);
+        assert!(cfg.blocks[&0].indirect);
+        assert_eq!(cfg.blocks[&0].successors, vec![(5, EdgeKind::FallThrough)]);
+        assert!(!cfg.blocks[&5].indirect);
+        assert_eq!(cfg.blocks[&5].successors, vec![(10, EdgeKind::Jump)]);
+    }
+
+    #[test]
+    fn dot_export() {
+        let ops = assemble(
+            "
+            loop:   out [x]
+                    jz [x], [loop]
+                    jnz #1, #loop
+            x:      .data 0
+            ",
+        )
+        .unwrap();
+        let cfg = ControlFlowGraph::build(&ops);
+        assert_eq!(
+            cfg.to_dot(&ops),
+            r#"digraph cfg {
+    node [shape=box, fontname="monospace"];
+    b0 [label="0: out [8]\l2: jz [8], [0]\l"];
+    b0 -> b5;
+    indirect0 [label="?", shape=circle];
+    b0 -> indirect0 [label="jump", style=dashed];
+    b5 [label="5: jnz #1, #0\l"];
+    b5 -> b0 [label="jump"];
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{IntcodeError, IntcodeProgram, IntcodeResult};
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+#[cfg(feature = "async")]
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+#[cfg(feature = "async")]
+use futures_core::Stream;
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Runs a program on its own thread, talking to it over `mpsc` channels. The thread
+/// blocks while the program waits for input, and finishes once it halts or `input` is
+/// dropped while it's waiting.
+#[derive(Debug)]
+pub struct ThreadedProgram {
+    pub input: Sender<isize>,
+    pub output: Receiver<isize>,
+    handle: JoinHandle<Result<IntcodeResult, IntcodeError>>,
+}
+
+impl ThreadedProgram {
+    pub fn spawn(mut program: IntcodeProgram) -> Self {
+        let (input, inputs) = mpsc::channel();
+        let (outputs, output) = mpsc::channel();
+
+        let handle = thread::spawn(move || loop {
+            match program.run()? {
+                IntcodeResult::Suspend(value) => {
+                    // Nobody listening isn't a reason to stop the program
+                    let _ = outputs.send(value);
+                }
+                IntcodeResult::NeedsInput => match inputs.recv() {
+                    Ok(value) => program.push_input(value),
+                    Err(_) => return Ok(IntcodeResult::NeedsInput),
+                },
+                IntcodeResult::BudgetExhausted => return Ok(IntcodeResult::BudgetExhausted),
+                IntcodeResult::Halt => return Ok(IntcodeResult::Halt),
+            }
+        });
+
+        Self {
+            input,
+            output,
+            handle,
+        }
+    }
+
+    /// Closes the input and waits for the program to finish, returning why it stopped.
+    /// Outputs that weren't received are dropped.
+    pub fn join(self) -> Result<IntcodeResult, IntcodeError> {
+        drop(self.input);
+        self.handle.join().expect("Intcode thread panicked")
+    }
+}
+
+/// The inputs of an `AsyncProgram`, which implements `Sink<isize>`
+#[cfg(feature = "async")]
+pub type InputSink = UnboundedSender<isize>;
+/// The outputs of an `AsyncProgram`, which implements `Stream<Item = isize>`
+#[cfg(feature = "async")]
+pub type OutputStream = UnboundedReceiver<isize>;
+
+/// A program run as a future. It returns `Pending` while waiting for input, so the
+/// executor can run other tasks, and resolves once the program halts or its input sink
+/// is closed while it's waiting. The output stream ends when it resolves.
+///
+/// A long stretch of instructions without any input runs without yielding. Setting a
+/// step budget makes the program yield each time the budget runs out, and it's
+/// refilled to the same size before resuming. A deadline isn't refilled, so once it
+/// passes the future resolves with `IntcodeResult::BudgetExhausted`.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncProgram {
+    program: IntcodeProgram,
+    inputs: UnboundedReceiver<isize>,
+    outputs: UnboundedSender<isize>,
+    slice: Option<u64>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncProgram {
+    pub fn new(program: IntcodeProgram) -> (Self, InputSink, OutputStream) {
+        let (input, inputs) = unbounded();
+        let (outputs, output) = unbounded();
+        let slice = program.step_budget();
+        (
+            Self {
+                program,
+                inputs,
+                outputs,
+                slice,
+            },
+            input,
+            output,
+        )
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for AsyncProgram {
+    type Output = Result<IntcodeResult, IntcodeError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            match this.program.run() {
+                Ok(IntcodeResult::Suspend(value)) => {
+                    let _ = this.outputs.unbounded_send(value);
+                }
+                Ok(IntcodeResult::NeedsInput) => match Pin::new(&mut this.inputs).poll_next(cx) {
+                    Poll::Ready(Some(value)) => this.program.push_input(value),
+                    Poll::Ready(None) => return this.finish(Ok(IntcodeResult::NeedsInput)),
+                    Poll::Pending => return Poll::Pending,
+                },
+                Ok(IntcodeResult::BudgetExhausted) => {
+                    if this.slice.is_none() || this.program.step_budget() != Some(0) {
+                        return this.finish(Ok(IntcodeResult::BudgetExhausted));
+                    }
+                    this.program.set_step_budget(this.slice);
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Ok(IntcodeResult::Halt) => return this.finish(Ok(IntcodeResult::Halt)),
+                Err(error) => return this.finish(Err(error)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncProgram {
+    fn finish(
+        &mut self,
+        result: Result<IntcodeResult, IntcodeError>,
+    ) -> Poll<Result<IntcodeResult, IntcodeError>> {
+        self.outputs.close_channel();
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    /// Outputs double each input, halting on zero
+    fn doubler() -> IntcodeProgram {
+        IntcodeProgram::new(
+            assemble(
+                "
+                loop:   in [x]
+                        jz [x], #done
+                        mul [x], #2, [x]
+                        out [x]
+                        jz #0, #loop
+                done:   hlt
+                x:      .data 0
+                ",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn threaded() {
+        let program = ThreadedProgram::spawn(doubler());
+        for value in 1..=3 {
+            program.input.send(value).unwrap();
+            assert_eq!(program.output.recv().unwrap(), value * 2);
+        }
+        assert_eq!(program.join(), Ok(IntcodeResult::NeedsInput));
+
+        let program = ThreadedProgram::spawn(doubler());
+        program.input.send(5).unwrap();
+        program.input.send(0).unwrap();
+        let output: Vec<isize> = program.output.iter().collect();
+        assert_eq!(output, vec![10]);
+        assert_eq!(program.join(), Ok(IntcodeResult::Halt));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn stream_and_sink() {
+        use futures::{executor::block_on, join, SinkExt, StreamExt};
+
+        let (program, mut input, mut output) = AsyncProgram::new(doubler());
+        let driver = async {
+            // Each output only arrives once the program has been polled again, so this
+            // relies on the program yielding while it waits
+            for value in 1..=3 {
+                input.send(value).await.unwrap();
+                assert_eq!(output.next().await, Some(value * 2));
+            }
+            input.send(0).await.unwrap();
+            output.collect::<Vec<isize>>().await
+        };
+
+        let (result, rest) = block_on(async { join!(program, driver) });
+        assert_eq!(result, Ok(IntcodeResult::Halt));
+        assert!(rest.is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn yields_on_budget() {
+        use futures::{executor::block_on, join, StreamExt};
+
+        let mut counter = IntcodeProgram::new(
+            assemble(
+                "
+                loop:   add [n], #1, [n]
+                        out [n]
+                        lt [n], #50, [t]
+                        jnz [t], #loop
+                        hlt
+                n:      .data 0
+                t:      .data 0
+                ",
+            )
+            .unwrap(),
+        );
+        counter.set_step_budget(Some(10));
+        let (program, _input, output) = AsyncProgram::new(counter);
+
+        let (result, outputs) = block_on(async { join!(program, output.collect::<Vec<_>>()) });
+        assert_eq!(result, Ok(IntcodeResult::Halt));
+        assert_eq!(outputs, (1..=50).collect::<Vec<isize>>());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn resolves_past_deadline() {
+        use futures::executor::block_on;
+        use std::time::{Duration, Instant};
+
+        // The step budget is refilled, but a deadline that has passed ends the run
+        for slice in [None, Some(10)] {
+            let mut looping = IntcodeProgram::new(assemble("loop: jz #0, #loop").unwrap());
+            looping.set_step_budget(slice);
+            looping.set_deadline(Some(Instant::now() - Duration::from_secs(1)));
+            let (program, _input, _output) = AsyncProgram::new(looping);
+            assert_eq!(block_on(program), Ok(IntcodeResult::BudgetExhausted));
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use crate::{disasm::describe_instruction, History, IntcodeProgram, IntcodeResult};
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+
+const HELP: &str = "\
+step [n]            (s)  execute n instructions (default 1)
+continue            (c)  run until a breakpoint, watchpoint, input request or halt
+rstep [n]           (rs) undo the last n instructions (default 1)
+rcontinue           (rc) run backwards until a breakpoint or the start of the history
+lastwrite <addr>         rewind to just before the last instruction that wrote to addr
+break <addr>        (b)  stop before executing the instruction at addr
+break op <opcode>        stop before executing any instruction with the opcode
+delete <addr>            remove an address breakpoint
+delete op <opcode>       remove an opcode breakpoint
+watch <addr>        (w)  stop after the value at addr changes
+unwatch <addr>           remove a watchpoint
+list                (l)  show breakpoints and watchpoints
+regs                (r)  show the step count, exec pointer, relative base and queued input
+mem <addr> [count]  (x)  show memory, one cell by default
+set <addr> <value>       write a value to memory
+input <values...>   (i)  queue values for the program to read
+help                (h)  show this message
+quit                (q)  leave the debugger";
+
+/// A line-driven debugger over an `IntcodeProgram`.
+///
+/// Commands are read one per line, so a session can be driven interactively from
+/// stdin or scripted from a string in tests. Execution is recorded in a `History`, so
+/// the program can also be stepped backwards.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    history: History,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&str>, name: &str) -> Result<T, String> {
+    let arg = arg.ok_or_else(|| format!("missing {}", name))?;
+    arg.parse()
+        .map_err(|_| format!("invalid {} `{}`", name, arg))
+}
+
+impl Debugger {
+    pub fn new(program: IntcodeProgram) -> Self {
+        Self {
+            history: History::new(program),
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn program(&self) -> &IntcodeProgram {
+        self.history.program()
+    }
+
+    fn location(&self) -> String {
+        let program = self.program();
+        if program.is_halted() {
+            return "program halted".to_string();
+        }
+
+        let address = program.exec_ptr();
+        let instruction = describe_instruction(&program.memory_window(address, 4), 0)
+            .unwrap_or_else(|| format!("<invalid {}>", program.peek_value(address)));
+        format!("{}: {}", address, instruction)
+    }
+
+    /// Executes one instruction, returning why execution should stop, if it should
+    fn step_once<W: Write>(&mut self, out: &mut W) -> io::Result<Option<String>> {
+        let watched: Vec<(usize, isize)> = self
+            .watchpoints
+            .iter()
+            .map(|address| (*address, self.program().peek_value(*address)))
+            .collect();
+
+        let stop = match self.history.step() {
+            Ok(Some(IntcodeResult::Suspend(output))) => {
+                writeln!(out, "output: {}", output)?;
+                None
+            }
+            Ok(Some(IntcodeResult::NeedsInput)) => Some("waiting for input".to_string()),
+            Ok(Some(IntcodeResult::Halt)) => Some("program halted".to_string()),
+            Ok(Some(IntcodeResult::BudgetExhausted)) => Some("step budget exhausted".to_string()),
+            Ok(None) => None,
+            Err(err) => return Ok(Some(format!("error: {}", err))),
+        };
+
+        for (address, old_value) in watched {
+            let new_value = self.program().peek_value(address);
+            if new_value != old_value {
+                return Ok(Some(format!(
+                    "watchpoint: [{}] changed from {} to {}",
+                    address, old_value, new_value
+                )));
+            }
+        }
+
+        Ok(stop)
+    }
+
+    fn breakpoint_hit(&self) -> Option<String> {
+        let address = self.program().exec_ptr();
+        let op = (self.program().peek_value(address) % 100) as usize;
+        if self.breakpoints.contains(&address) {
+            Some(format!("breakpoint at {}", address))
+        } else if self.opcode_breakpoints.contains(&op) {
+            Some(format!("breakpoint on opcode {}", op))
+        } else {
+            None
+        }
+    }
+
+    fn step<W: Write>(&mut self, count: usize, out: &mut W) -> io::Result<()> {
+        for _ in 0..count {
+            if let Some(reason) = self.step_once(out)? {
+                writeln!(out, "{}", reason)?;
+                break;
+            }
+        }
+        writeln!(out, "{}", self.location())
+    }
+
+    fn continue_execution<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        // Always make progress, even when sitting on a breakpoint
+        let mut reason = self.step_once(out)?;
+        while reason.is_none() {
+            reason = self.breakpoint_hit();
+            if reason.is_none() {
+                reason = self.step_once(out)?;
+            }
+        }
+
+        if let Some(reason) = reason {
+            writeln!(out, "{}", reason)?;
+        }
+        writeln!(out, "{}", self.location())
+    }
+
+    fn reverse_step<W: Write>(&mut self, count: usize, out: &mut W) -> io::Result<()> {
+        for _ in 0..count {
+            if !self.history.reverse_step() {
+                writeln!(out, "reached the start of the history")?;
+                break;
+            }
+        }
+        writeln!(out, "{}", self.location())
+    }
+
+    fn reverse_continue<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        if self.history.reverse_continue(&self.breakpoints) {
+            writeln!(out, "breakpoint at {}", self.program().exec_ptr())?;
+        } else {
+            writeln!(out, "reached the start of the history")?;
+        }
+        writeln!(out, "{}", self.location())
+    }
+
+    fn rewind_to_last_write<W: Write>(&mut self, address: usize, out: &mut W) -> io::Result<()> {
+        if self.history.rewind_to_last_write(address) {
+            writeln!(out, "rewound to step {}", self.history.steps())?;
+        } else {
+            writeln!(out, "no recorded write to {}", address)?;
+        }
+        writeln!(out, "{}", self.location())
+    }
+
+    fn show_memory<W: Write>(&self, addresses: Range<usize>, out: &mut W) -> io::Result<()> {
+        for address in addresses {
+            writeln!(
+                out,
+                "[{}] = {}",
+                address,
+                self.program().peek_value(address)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn list<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for address in self.breakpoints.iter() {
+            writeln!(out, "break {}", address)?;
+        }
+        for op in self.opcode_breakpoints.iter() {
+            writeln!(out, "break op {}", op)?;
+        }
+        for address in self.watchpoints.iter() {
+            writeln!(out, "watch {}", address)?;
+        }
+        Ok(())
+    }
+
+    fn run_command<W: Write>(&mut self, command: &str, out: &mut W) -> Result<bool, String> {
+        let mut words = command.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(true),
+        };
+        let io_error = |err: io::Error| err.to_string();
+
+        match name {
+            "step" | "s" => {
+                let count = match words.next() {
+                    Some(count) => parse_arg(Some(count), "step count")?,
+                    None => 1,
+                };
+                self.step(count, out).map_err(io_error)?;
+            }
+            "continue" | "c" => self.continue_execution(out).map_err(io_error)?,
+            "rstep" | "rs" => {
+                let count = match words.next() {
+                    Some(count) => parse_arg(Some(count), "step count")?,
+                    None => 1,
+                };
+                self.reverse_step(count, out).map_err(io_error)?;
+            }
+            "rcontinue" | "rc" => self.reverse_continue(out).map_err(io_error)?,
+            "lastwrite" => {
+                let address = parse_arg(words.next(), "address")?;
+                self.rewind_to_last_write(address, out).map_err(io_error)?;
+            }
+            "break" | "b" => match words.next() {
+                Some("op") => {
+                    self.opcode_breakpoints
+                        .insert(parse_arg(words.next(), "opcode")?);
+                }
+                address => {
+                    self.breakpoints.insert(parse_arg(address, "address")?);
+                }
+            },
+            "delete" => match words.next() {
+                Some("op") => {
+                    self.opcode_breakpoints
+                        .remove(&parse_arg(words.next(), "opcode")?);
+                }
+                address => {
+                    self.breakpoints.remove(&parse_arg(address, "address")?);
+                }
+            },
+            "watch" | "w" => {
+                self.watchpoints.insert(parse_arg(words.next(), "address")?);
+            }
+            "unwatch" => {
+                self.watchpoints
+                    .remove(&parse_arg(words.next(), "address")?);
+            }
+            "list" | "l" => self.list(out).map_err(io_error)?,
+            "regs" | "r" => {
+                let program = self.history.program();
+                let pending: Vec<String> = program
+                    .pending_input()
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect();
+                writeln!(
+                    out,
+                    "step: {}\nexec_ptr: {}\nrelative_base: {}\ninput: [{}]",
+                    self.history.steps(),
+                    program.exec_ptr(),
+                    program.relative_base(),
+                    pending.join(", ")
+                )
+                .map_err(io_error)?;
+            }
+            "mem" | "x" => {
+                let start: usize = parse_arg(words.next(), "address")?;
+                let count: usize = match words.next() {
+                    Some(count) => parse_arg(Some(count), "count")?,
+                    None => 1,
+                };
+                // Parameters are `isize`, so no program can reach past `isize::MAX`
+                let end = start
+                    .checked_add(count)
+                    .filter(|end| *end <= isize::MAX as usize + 1)
+                    .ok_or_else(|| {
+                        format!("{} cells from {} is past the last address", count, start)
+                    })?;
+                self.show_memory(start..end, out).map_err(io_error)?;
+            }
+            "set" => {
+                let address = parse_arg(words.next(), "address")?;
+                let value = parse_arg(words.next(), "value")?;
+                if let Some(limit) = self.program().memory_limit() {
+                    if address >= limit {
+                        return Err(format!(
+                            "address {} is past the memory limit of {} cells",
+                            address, limit
+                        ));
+                    }
+                }
+                self.history.set_value(address, value);
+            }
+            "input" | "i" => {
+                let values: Vec<isize> = words
+                    .map(|word| parse_arg(Some(word), "input value"))
+                    .collect::<Result<_, _>>()?;
+                self.history.extend_inputs(values);
+            }
+            "help" | "h" => writeln!(out, "{}", HELP).map_err(io_error)?,
+            "quit" | "q" => return Ok(false),
+            _ => return Err(format!("unknown command `{}`, try `help`", name)),
+        }
+
+        Ok(true)
+    }
+
+    /// Runs a single command, returning false once the session should end
+    pub fn execute<W: Write>(&mut self, command: &str, out: &mut W) -> io::Result<bool> {
+        match self.run_command(command, out) {
+            Ok(keep_going) => Ok(keep_going),
+            Err(message) => {
+                writeln!(out, "error: {}", message)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Reads and runs commands until `quit` or the end of the input
+    pub fn run_session<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", self.location())?;
+        for line in input.lines() {
+            if !self.execute(&line?, &mut out)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    fn session(ops: Vec<isize>, script: &str) -> String {
+        let mut debugger = Debugger::new(IntcodeProgram::new(ops));
+        let mut out = Vec::new();
+        debugger.run_session(script.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn step_and_inspect() {
+        let ops = assemble(
+            "
+                arb #3
+                add #2, #3, [20]
+                out [20]
+                hlt
+            ",
+        )
+        .unwrap();
+        let output = session(ops, "s\nregs\ns 2\nx 20\nc\nq\n");
+        assert_eq!(
+            output,
+            "0: arb #3
+2: add #2, #3, [20]
+step: 1
+exec_ptr: 2
+relative_base: 3
+input: []
+output: 5
+8: hlt
+[20] = 5
+program halted
+program halted
+"
+        );
+    }
+
+    #[test]
+    fn breakpoints_and_input() {
+        // Count down from the input value, outputting each step
+        let ops = assemble(
+            "
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    hlt
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+
+        let output = session(ops.clone(), "c\ni 3\nb 2\nc\nc\nregs\ndelete 2\nc\n");
+        assert_eq!(
+            output,
+            "0: in [12]
+waiting for input
+0: in [12]
+breakpoint at 2
+2: out [12]
+output: 3
+breakpoint at 2
+2: out [12]
+step: 4
+exec_ptr: 2
+relative_base: 0
+input: []
+output: 2
+output: 1
+program halted
+program halted
+"
+        );
+
+        let output = session(ops, "i 2\nb op 4\nc\nw 12\nc\nc\nl\n");
+        assert_eq!(
+            output,
+            "0: in [12]
+breakpoint on opcode 4
+2: out [12]
+output: 2
+watchpoint: [12] changed from 2 to 1
+8: jnz [12], #2
+breakpoint on opcode 4
+2: out [12]
+break op 4
+watch 12
+"
+        );
+    }
+
+    #[test]
+    fn edit_memory_and_errors() {
+        let output = session(vec![4, 5, 99, 0, 0, 7], "set 5 42\nc\nbogus\nx\n");
+        assert_eq!(
+            output,
+            "0: out [5]
+output: 42
+program halted
+program halted
+error: unknown command `bogus`, try `help`
+error: missing address
+"
+        );
+
+        let output = session(
+            vec![99],
+            "mem 9223372036854775807 10\nx 18446744073709551615 2\nx 1 2\n",
+        );
+        assert_eq!(
+            output,
+            "0: hlt
+error: 10 cells from 9223372036854775807 is past the last address
+error: 2 cells from 18446744073709551615 is past the last address
+[1] = 0
+[2] = 0
+"
+        );
+
+        let output = session(vec![99], "set 100000000 1\nx 3\n");
+        assert_eq!(
+            output,
+            "0: hlt
+error: address 100000000 is past the memory limit of 16777216 cells
+[3] = 0
+"
+        );
+
+        let output = session(vec![42], "s\n");
+        assert_eq!(
+            output,
+            "0: <invalid 42>
+error: unknown opcode 42 at address 0
+0: <invalid 42>
+"
+        );
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let ops = assemble(
+            "
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    hlt
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+
+        let output = session(
+            ops,
+            "i 2\nc\nrs 2\nb 2\nrc\nlastwrite 12\nrs 9\nlastwrite 1\n",
+        );
+        assert_eq!(
+            output,
+            "0: in [12]
+output: 2
+output: 1
+program halted
+program halted
+8: jnz [12], #2
+breakpoint at 2
+2: out [12]
+rewound to step 2
+4: add [12], #-1, [12]
+reached the start of the history
+0: in [12]
+no recorded write to 1
+0: in [12]
+"
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use crate::ascii::{encode_line, render};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Stdin, Stdout, Write},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// Something a program exchanges values with, in place of its input queue and
+/// suspending on output.
+///
+/// A device can also claim a range of addresses, so that parameters reading or writing
+/// them go to the device instead of memory. Instruction fetches always come from
+/// memory.
+pub trait IoDevice {
+    /// The next input value, or `None` if there isn't one yet, which pauses the program
+    /// as `IntcodeResult::NeedsInput`
+    fn input(&mut self) -> io::Result<Option<isize>>;
+
+    fn output(&mut self, value: isize) -> io::Result<()>;
+
+    /// The addresses handled by `read` and `write`. This is asked once, when the device
+    /// is attached.
+    fn mapped_range(&self) -> Option<Range<usize>> {
+        None
+    }
+
+    fn read(&mut self, _address: usize) -> io::Result<isize> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _address: usize, _value: isize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub type SharedDevice = Arc<Mutex<dyn IoDevice + Send>>;
+
+/// Input and output queues, for feeding a program values up front and collecting what
+/// it produces
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueDevice {
+    pub input: VecDeque<isize>,
+    pub output: Vec<isize>,
+}
+
+impl QueueDevice {
+    pub fn new<I: IntoIterator<Item = isize>>(input: I) -> Self {
+        Self {
+            input: input.into_iter().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl IoDevice for QueueDevice {
+    fn input(&mut self) -> io::Result<Option<isize>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn output(&mut self, value: isize) -> io::Result<()> {
+        self.output.push(value);
+        Ok(())
+    }
+}
+
+/// Hands input and output to a pair of closures
+pub struct FnDevice<I, O> {
+    input: I,
+    output: O,
+}
+
+impl<I, O> FnDevice<I, O>
+where
+    I: FnMut() -> Option<isize>,
+    O: FnMut(isize),
+{
+    pub fn new(input: I, output: O) -> Self {
+        Self { input, output }
+    }
+}
+
+impl<I, O> IoDevice for FnDevice<I, O>
+where
+    I: FnMut() -> Option<isize>,
+    O: FnMut(isize),
+{
+    fn input(&mut self) -> io::Result<Option<isize>> {
+        Ok((self.input)())
+    }
+
+    fn output(&mut self, value: isize) -> io::Result<()> {
+        (self.output)(value);
+        Ok(())
+    }
+}
+
+impl<I, O> fmt::Debug for FnDevice<I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FnDevice")
+    }
+}
+
+/// Reads whitespace or comma separated numbers, and writes each output on its own line
+#[derive(Debug)]
+pub struct NumericStream<R, W> {
+    reader: R,
+    writer: W,
+    pending: VecDeque<isize>,
+}
+
+impl<R: BufRead, W: Write> NumericStream<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl NumericStream<BufReader<Stdin>, Stdout> {
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> IoDevice for NumericStream<R, W> {
+    fn input(&mut self) -> io::Result<Option<isize>> {
+        self.writer.flush()?;
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            for token in line.split(|c: char| c == ',' || c.is_whitespace()) {
+                if token.is_empty() {
+                    continue;
+                }
+                let value = token.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("`{}` is not a number", token),
+                    )
+                })?;
+                self.pending.push_back(value);
+            }
+        }
+
+        Ok(self.pending.pop_front())
+    }
+
+    fn output(&mut self, value: isize) -> io::Result<()> {
+        writeln!(self.writer, "{}", value)
+    }
+}
+
+/// Exchanges text, one character per value. Input is read a line at a time, ending
+/// with a newline, and outputs outside the ASCII range are written as numbers on their
+/// own line, as puzzles use them for a final answer.
+#[derive(Debug)]
+pub struct AsciiDevice<R, W> {
+    reader: R,
+    writer: W,
+    pending: VecDeque<isize>,
+}
+
+impl<R: BufRead, W: Write> AsciiDevice<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl AsciiDevice<BufReader<Stdin>, Stdout> {
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> IoDevice for AsciiDevice<R, W> {
+    fn input(&mut self) -> io::Result<Option<isize>> {
+        if self.pending.is_empty() {
+            self.writer.flush()?;
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let codes = encode_line(&line)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+            self.pending.extend(codes);
+        }
+
+        Ok(self.pending.pop_front())
+    }
+
+    fn output(&mut self, value: isize) -> io::Result<()> {
+        let mut text = String::new();
+        render(value, &mut text);
+        self.writer.write_all(text.as_bytes())
+    }
+}
+
+/// The device slot on a program, along with the range it maps. Devices are
+/// configuration rather than state, so they are ignored when comparing programs.
+#[derive(Clone, Default)]
+pub(crate) struct DeviceSlot {
+    pub(crate) device: Option<SharedDevice>,
+    pub(crate) mapped: Option<Range<usize>>,
+}
+
+impl DeviceSlot {
+    pub(crate) fn new(device: SharedDevice) -> Self {
+        let mapped = device
+            .lock()
+            .expect("Device lock was poisoned")
+            .mapped_range();
+        Self {
+            device: Some(device),
+            mapped,
+        }
+    }
+
+    /// The device, if one is attached and `address` is mapped to it
+    pub(crate) fn mapping(&self, address: usize) -> Option<&SharedDevice> {
+        match &self.mapped {
+            Some(range) if range.contains(&address) => self.device.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for DeviceSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.device, &self.mapped) {
+            (None, _) => write!(f, "DeviceSlot(none)"),
+            (Some(_), None) => write!(f, "DeviceSlot(attached)"),
+            (Some(_), Some(range)) => write!(f, "DeviceSlot(attached, mapped {:?})", range),
+        }
+    }
+}
+
+impl PartialEq for DeviceSlot {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DeviceSlot {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assemble, History, IntcodeError, IntcodeProgram, IntcodeResult};
+    use std::io::Cursor;
+
+    /// Doubles each input until the input runs out
+    fn doubler() -> Vec<isize> {
+        assemble(
+            "
+            loop:   in [n]
+                    mul [n], #2, [n]
+                    out [n]
+                    jnz #1, #loop
+            n:      .data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn queue_device() {
+        let device = Arc::new(Mutex::new(QueueDevice::new(vec![1, 2])));
+        let mut program = IntcodeProgram::new(doubler());
+        program.attach_device(device.clone());
+        program.push_input(5);
+
+        // Queued input is read before the device is asked
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(device.lock().unwrap().output, vec![10, 2, 4]);
+
+        device.lock().unwrap().input.push_back(3);
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(device.lock().unwrap().output, vec![10, 2, 4, 6]);
+    }
+
+    #[test]
+    fn closure_device() {
+        let outputs = Arc::new(Mutex::new(Vec::new()));
+        let sink = outputs.clone();
+        let mut next = 0;
+        let device = FnDevice::new(
+            move || {
+                next += 1;
+                if next <= 3 {
+                    Some(next)
+                } else {
+                    None
+                }
+            },
+            move |value| sink.lock().unwrap().push(value),
+        );
+
+        let mut program = IntcodeProgram::new(doubler());
+        program.attach_device(Arc::new(Mutex::new(device)));
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(*outputs.lock().unwrap(), vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn numeric_stream() {
+        let stream = NumericStream::new(Cursor::new("4, 5\n\n 6\n"), Vec::new());
+        let device = Arc::new(Mutex::new(stream));
+        let mut program = IntcodeProgram::new(doubler());
+        program.attach_device(device.clone());
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        program.detach_device();
+
+        let device = Arc::try_unwrap(device).ok().unwrap().into_inner().unwrap();
+        let (_, written) = device.into_inner();
+        assert_eq!(String::from_utf8(written).unwrap(), "8\n10\n12\n");
+
+        let stream = NumericStream::new(Cursor::new("7 x"), Vec::new());
+        let mut program = IntcodeProgram::new(doubler());
+        program.attach_device(Arc::new(Mutex::new(stream)));
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::Device {
+                address: 0,
+                message: "`x` is not a number".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn ascii_device() {
+        // Echoes each character, then outputs a hundred times the number of characters
+        // once it reads a newline
+        let ops = assemble(
+            "
+            loop:   in [c]
+                    eq [c], #10, [done]
+                    jnz [done], #end
+                    out [c]
+                    add [count], #1, [count]
+                    jz #0, #loop
+            end:    out #10
+                    mul [count], #100, [count]
+                    out [count]
+                    hlt
+            c:      .data 0
+            done:   .data 0
+            count:  .data 0
+            ",
+        )
+        .unwrap();
+        let device = Arc::new(Mutex::new(AsciiDevice::new(
+            Cursor::new("hi there\n"),
+            Vec::new(),
+        )));
+        let mut program = IntcodeProgram::new(ops);
+        program.attach_device(device.clone());
+        assert_eq!(program.run_to_halt(), Ok(vec![]));
+        program.detach_device();
+
+        let device = Arc::try_unwrap(device).ok().unwrap().into_inner().unwrap();
+        let (_, written) = device.into_inner();
+        assert_eq!(String::from_utf8(written).unwrap(), "hi there\n800\n");
+    }
+
+    #[test]
+    fn history_rewinds_over_device_input() {
+        let device = Arc::new(Mutex::new(QueueDevice::new(vec![1, 2, 3])));
+        let mut program = IntcodeProgram::new(doubler());
+        program.attach_device(device.clone());
+
+        // Keeping only two undo records makes rewinding replay from the first
+        // checkpoint, which has to queue the device's input rather than ask for more
+        let mut history = History::with_limits(program, 2, 100, 1);
+        assert_eq!(history.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(history.steps(), 12);
+        assert!(history.rewind_to(2));
+        assert_eq!(history.program().pending_input(), &[2, 3]);
+
+        assert_eq!(history.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(device.lock().unwrap().output, vec![2, 4, 6, 2, 4, 6]);
+    }
+
+    /// Maps a counter at address 1000 and a register that records writes at 1001
+    #[derive(Default)]
+    struct Registers {
+        reads: isize,
+        written: Vec<isize>,
+    }
+
+    impl IoDevice for Registers {
+        fn input(&mut self) -> io::Result<Option<isize>> {
+            Ok(None)
+        }
+
+        fn output(&mut self, _value: isize) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn mapped_range(&self) -> Option<Range<usize>> {
+            Some(1000..1002)
+        }
+
+        fn read(&mut self, _address: usize) -> io::Result<isize> {
+            self.reads += 1;
+            Ok(self.reads * 10)
+        }
+
+        fn write(&mut self, _address: usize, value: isize) -> io::Result<()> {
+            self.written.push(value);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn memory_mapped_range() {
+        let ops = assemble(
+            "
+                    add [1000], [1000], [1001]
+                    add [1001], #5, [x]
+                    hlt
+            x:      .data 0
+            ",
+        )
+        .unwrap();
+        let device = Arc::new(Mutex::new(Registers::default()));
+        let mut program = IntcodeProgram::new(ops);
+        program.attach_device(device.clone());
+        program.run_to_halt().unwrap();
+
+        assert_eq!(device.lock().unwrap().written, vec![30]);
+        // The mapped cells never reach memory, so the read of 1001 goes to the device
+        assert_eq!(program.memory_len(), 10);
+        assert_eq!(program.peek_value(9), 35);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{asm::MNEMONICS, parse_op, ArgMode, EdgeKind, MODE_IMM, MODE_POS};
+use std::collections::BTreeSet;
+
+/// The most values listed on a single `.data` line
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Decoded {
+    pub(crate) op: usize,
+    pub(crate) arg_modes: Vec<ArgMode>,
+}
+
+impl Decoded {
+    pub(crate) fn len(&self) -> usize {
+        self.arg_modes.len() + 1
+    }
+}
+
+pub(crate) fn decode(ops: &[isize], address: usize) -> Option<Decoded> {
+    let (op, arg_modes) = parse_op(address, ops[address]).ok()?;
+    if address + arg_modes.len() >= ops.len() {
+        return None;
+    }
+
+    Some(Decoded { op, arg_modes })
+}
+
+/// Where execution can go after the instruction at `address`, for every target that
+/// can be worked out without running the program
+pub(crate) fn successors(
+    ops: &[isize],
+    address: usize,
+    decoded: &Decoded,
+) -> Vec<(usize, EdgeKind)> {
+    let next = address + decoded.len();
+    match decoded.op {
+        99 => vec![],
+        5 | 6 => {
+            let mut targets = Vec::new();
+
+            // An immediate condition always goes the same way, which lets us spot the
+            // unconditional `1105,1,x` style jumps that often sit just before data
+            let condition = ops[address + 1];
+            let always = match (decoded.arg_modes[0], decoded.op) {
+                (MODE_IMM, 5) => Some(condition != 0),
+                (MODE_IMM, 6) => Some(condition == 0),
+                _ => None,
+            };
+
+            if always != Some(true) {
+                targets.push((next, EdgeKind::FallThrough));
+            }
+            if always != Some(false) && decoded.arg_modes[1] == MODE_IMM {
+                let target = ops[address + 2];
+                if target >= 0 {
+                    targets.push((target as usize, EdgeKind::Jump));
+                }
+            }
+            targets
+        }
+        _ => vec![(next, EdgeKind::FallThrough)],
+    }
+}
+
+/// Finds the start of every instruction reachable from address 0 by following
+/// fall-through and immediate jumps. Anything else is assumed to be data.
+pub(crate) fn find_code(ops: &[isize]) -> BTreeSet<usize> {
+    let mut code = BTreeSet::new();
+    let mut to_visit = vec![0];
+
+    while let Some(address) = to_visit.pop() {
+        if address >= ops.len() || code.contains(&address) {
+            continue;
+        }
+        if let Some(decoded) = decode(ops, address) {
+            code.insert(address);
+            to_visit.extend(
+                successors(ops, address, &decoded)
+                    .into_iter()
+                    .map(|(target, _)| target),
+            );
+        }
+    }
+
+    code
+}
+
+enum Line {
+    Instruction(usize, Decoded),
+    Data(usize, usize),
+}
+
+fn format_param(value: isize, mode: ArgMode) -> String {
+    match mode {
+        MODE_POS => format!("[{}]", value),
+        MODE_IMM => format!("#{}", value),
+        _ => format!("rel({})", value),
+    }
+}
+
+pub(crate) fn format_instruction<F>(
+    ops: &[isize],
+    address: usize,
+    decoded: &Decoded,
+    label_at: F,
+) -> String
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    let mnemonic = MNEMONICS
+        .iter()
+        .find(|(op, _)| *op == decoded.op)
+        .map(|(_, mnemonic)| *mnemonic)
+        .expect("decoded an opcode without a mnemonic");
+    let params: Vec<String> = decoded
+        .arg_modes
+        .iter()
+        .enumerate()
+        .map(|(idx, mode)| {
+            let value = ops[address + 1 + idx];
+            let is_target = (decoded.op == 5 || decoded.op == 6) && idx == 1;
+            if is_target && *mode == MODE_IMM && value >= 0 {
+                if let Some(target) = label_at(value as usize) {
+                    return format!("#L{}", target);
+                }
+            }
+            format_param(value, *mode)
+        })
+        .collect();
+
+    if params.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, params.join(", "))
+    }
+}
+
+/// Renders the single instruction at `address`, or `None` if it does not decode
+pub(crate) fn describe_instruction(ops: &[isize], address: usize) -> Option<String> {
+    if address >= ops.len() {
+        return None;
+    }
+    let decoded = decode(ops, address)?;
+    Some(format_instruction(ops, address, &decoded, |_| None))
+}
+
+fn format_line(label: Option<usize>, body: &str, address: usize) -> String {
+    let label = label
+        .map(|target| format!("L{}:", target))
+        .unwrap_or_default();
+    format!("{:<8} {:<40} ; {}", label, body, address)
+}
+
+/// Renders a memory image as an annotated listing in the syntax accepted by `assemble`.
+///
+/// Instructions reachable from the start of the program are shown with their mnemonic
+/// and parameter modes, and everything else is listed as `.data`. Immediate jump
+/// targets are given `L<address>` labels, and each line ends with its address.
+pub fn disassemble(ops: &[isize]) -> String {
+    let code = find_code(ops);
+
+    // Lay out the lines first, so we know which addresses a label can be placed on
+    let mut lines = Vec::new();
+    let mut jump_targets = BTreeSet::new();
+    let mut address = 0;
+    while address < ops.len() {
+        if code.contains(&address) {
+            let decoded = decode(ops, address).expect("code addresses should decode");
+            if (decoded.op == 5 || decoded.op == 6) && decoded.arg_modes[1] == MODE_IMM {
+                jump_targets.insert(ops[address + 2]);
+            }
+            let len = decoded.len();
+            lines.push(Line::Instruction(address, decoded));
+            address += len;
+        } else {
+            let start = address;
+            address += 1;
+            while address < ops.len() && !code.contains(&address) {
+                address += 1;
+            }
+            lines.push(Line::Data(start, address));
+        }
+    }
+
+    let line_starts: BTreeSet<usize> = lines
+        .iter()
+        .flat_map(|line| match line {
+            Line::Instruction(address, _) => *address..*address + 1,
+            Line::Data(start, end) => *start..*end,
+        })
+        .collect();
+    let labels: BTreeSet<usize> = jump_targets
+        .into_iter()
+        .filter(|target| *target >= 0 && line_starts.contains(&(*target as usize)))
+        .map(|target| target as usize)
+        .collect();
+    let label_at = |address: usize| Some(address).filter(|address| labels.contains(address));
+
+    let mut listing = Vec::new();
+    for line in lines {
+        match line {
+            Line::Instruction(address, decoded) => {
+                let body = format_instruction(ops, address, &decoded, label_at);
+                listing.push(format_line(label_at(address), &body, address));
+            }
+            Line::Data(start, end) => {
+                // Break data runs at labels and every few values to keep lines short
+                let mut chunk_start = start;
+                while chunk_start < end {
+                    let mut chunk_end = chunk_start + 1;
+                    while chunk_end < end
+                        && chunk_end - chunk_start < DATA_PER_LINE
+                        && !labels.contains(&chunk_end)
+                    {
+                        chunk_end += 1;
+                    }
+                    let values: Vec<String> = ops[chunk_start..chunk_end]
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect();
+                    let body = format!(".data {}", values.join(", "));
+                    listing.push(format_line(label_at(chunk_start), &body, chunk_start));
+                    chunk_start = chunk_end;
+                }
+            }
+        }
+    }
+
+    let mut listing = listing
+        .into_iter()
+        .map(|line| line.trim_end().to_string())
+        .collect::<Vec<String>>()
+        .join("\n");
+    listing.push('\n');
+    listing
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn listing() {
+        let ops = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let expected = "         in [26]                                  ; 0
+         add [26], #-4, [26]                      ; 2
+L6:      in [27]                                  ; 6
+         mul [27], #2, [27]                       ; 8
+         add [27], [26], [27]                     ; 12
+         out [27]                                 ; 16
+         add [28], #-1, [28]                      ; 18
+         jnz [28], #L6                            ; 22
+         hlt                                      ; 25
+         .data 0, 0, 5                            ; 26
+";
+        assert_eq!(disassemble(&ops), expected);
+    }
+
+    #[test]
+    fn data_after_unconditional_jump() {
+        // The 104 after the jump is never reached, so it should not be decoded
+        let ops = vec![1105, 1, 5, 104, 7, 204, -1, 99];
+        let listing = disassemble(&ops);
+        assert!(listing.contains("jnz #1, #L5"));
+        assert!(listing.contains(".data 104, 7"));
+        assert!(listing.contains("L5:      out rel(-1)"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let programs = vec![
+            vec![
+                109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+            ],
+            vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0],
+            vec![
+                3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36,
+                98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000,
+                1, 20, 4, 20, 1105, 1, 46, 98, 99,
+            ],
+            vec![42, -1, 3],
+        ];
+
+        for ops in programs {
+            assert_eq!(assemble(&disassemble(&ops)), Ok(ops));
+        }
+    }
+}
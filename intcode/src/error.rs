@@ -0,0 +1,98 @@
+use std::{error::Error, fmt};
+
+/// Everything that can go wrong while decoding or executing an Intcode program.
+///
+/// Addresses refer to the instruction being executed when the error occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntcodeError {
+    UnknownOpcode {
+        address: usize,
+        opcode: isize,
+    },
+    InvalidArgMode {
+        address: usize,
+        opcode: isize,
+    },
+    ImmediateWrite {
+        address: usize,
+    },
+    NegativeAddress {
+        address: usize,
+        target: isize,
+    },
+    InputExhausted {
+        address: usize,
+    },
+    PointerOutOfBounds {
+        address: usize,
+    },
+    /// An access to `target`, which is past the program's memory limit
+    MemoryLimit {
+        address: usize,
+        target: usize,
+    },
+    Overflow {
+        address: usize,
+    },
+    BudgetExhausted {
+        address: usize,
+    },
+    /// An attached I/O device failed
+    Device {
+        address: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntcodeError::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {} at address {}", opcode, address)
+            }
+            IntcodeError::InvalidArgMode { address, opcode } => write!(
+                f,
+                "invalid parameter mode in opcode {} at address {}",
+                opcode, address
+            ),
+            IntcodeError::ImmediateWrite { address } => write!(
+                f,
+                "instruction at address {} writes to an immediate mode parameter",
+                address
+            ),
+            IntcodeError::NegativeAddress { address, target } => write!(
+                f,
+                "instruction at address {} accessed negative address {}",
+                address, target
+            ),
+            IntcodeError::InputExhausted { address } => write!(
+                f,
+                "instruction at address {} required input but none was remaining",
+                address
+            ),
+            IntcodeError::PointerOutOfBounds { address } => write!(
+                f,
+                "execution ran off the end of memory at address {}",
+                address
+            ),
+            IntcodeError::MemoryLimit { address, target } => write!(
+                f,
+                "instruction at address {} accessed address {}, past the memory limit",
+                address, target
+            ),
+            IntcodeError::Overflow { address } => {
+                write!(f, "arithmetic overflow at address {}", address)
+            }
+            IntcodeError::Device { address, message } => {
+                write!(f, "I/O device failed at address {}: {}", address, message)
+            }
+            IntcodeError::BudgetExhausted { address } => write!(
+                f,
+                "ran out of step budget or time before address {}",
+                address
+            ),
+        }
+    }
+}
+
+impl Error for IntcodeError {}
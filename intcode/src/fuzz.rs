@@ -0,0 +1,1007 @@
+//! Differential fuzzing of the Intcode machine.
+//!
+//! Each configuration of `IntcodeProgram` is compared against two kinds of oracle that
+//! share none of its code. The machines the days ran before the shared one replaced
+//! them are kept in `baseline`, each covering the opcodes of its own day, so a
+//! regression from moving to the shared machine shows up against the code it
+//! replaced. `run_reference` is a separate interpreter covering every opcode and
+//! policy, which does its own decoding and doesn't use `Instruction` or
+//! `OpcodeTable`, so a decoding bug can't show up on both sides.
+//!
+//! The overflow policies disagree by design, so each one gets its own group of
+//! implementations to compare.
+
+use crate::{
+    baseline, BigInt, History, Instruction, IntcodeError, IntcodeProgram, IntcodeResult, IoDevice,
+    MemoryKind, NullTracer, OpcodeTable, OverflowPolicy, Snapshot, MODE_IMM, MODE_POS, MODE_REL,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    fmt, io,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// How many instructions each implementation may run on a generated program
+pub const FUZZ_MAX_STEPS: u64 = 500;
+
+/// Generated programs that touch memory past this are skipped rather than compared, so
+/// that a stray huge address can't make the dense implementations allocate it all
+pub const FUZZ_MAX_MEMORY: usize = 1 << 16;
+
+/// The overflow policies to compare implementations under
+pub const FUZZ_POLICIES: [OverflowPolicy; 4] = [
+    OverflowPolicy::Error,
+    OverflowPolicy::Wrap,
+    OverflowPolicy::Widen,
+    OverflowPolicy::Arbitrary,
+];
+
+/// The opcodes and parameter modes of each stage of the puzzles. Day 7 runs the same
+/// machine as day 5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeSet {
+    /// `add`, `mul` and `hlt` in position mode
+    Day2,
+    /// Adds I/O, jumps and comparisons, and immediate mode
+    Day5,
+    /// Adds `arb` and relative mode
+    Day9,
+}
+
+impl OpcodeSet {
+    pub const ALL: [OpcodeSet; 3] = [OpcodeSet::Day2, OpcodeSet::Day5, OpcodeSet::Day9];
+
+    /// The opcodes other than `hlt`
+    pub fn opcodes(&self) -> &'static [isize] {
+        match self {
+            OpcodeSet::Day2 => &[1, 2],
+            OpcodeSet::Day5 => &[1, 2, 3, 4, 5, 6, 7, 8],
+            OpcodeSet::Day9 => &[1, 2, 3, 4, 5, 6, 7, 8, 9],
+        }
+    }
+
+    pub fn modes(&self) -> &'static [isize] {
+        match self {
+            OpcodeSet::Day2 => &[MODE_POS as isize],
+            OpcodeSet::Day5 => &[MODE_POS as isize, MODE_IMM as isize],
+            OpcodeSet::Day9 => &[MODE_POS as isize, MODE_IMM as isize, MODE_REL as isize],
+        }
+    }
+}
+
+/// A xorshift generator, so that every case can be reproduced from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck on zero, and nearby seeds should still differ
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    /// A value in `low..=high`
+    fn between(&mut self, low: isize, high: isize) -> isize {
+        low + self.below((high - low + 1) as usize) as isize
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+/// A program to run, along with the input given to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub program: Vec<isize>,
+    pub inputs: Vec<isize>,
+}
+
+impl Case {
+    /// Generates a random program using only the opcodes and modes in `set`.
+    ///
+    /// Programs are mostly well formed, with jumps landing on instructions and
+    /// addresses pointing into the program, but are free to overwrite their own code,
+    /// loop forever, overflow or fail.
+    pub fn generate(seed: u64, set: OpcodeSet) -> Self {
+        let mut rng = Rng::new(seed);
+
+        let count = rng.between(3, 16) as usize;
+        let mut instructions: Vec<(isize, Vec<isize>)> = (0..count)
+            .map(|index| {
+                let opcode = if index == count - 1 || rng.one_in(20) {
+                    99
+                } else {
+                    rng.pick(set.opcodes())
+                };
+                let arity = match opcode {
+                    1 | 2 | 7 | 8 => 3,
+                    5 | 6 => 2,
+                    3 | 4 | 9 => 1,
+                    _ => 0,
+                };
+                let modes = (0..arity).map(|_| rng.pick(set.modes())).collect();
+                (opcode, modes)
+            })
+            .collect();
+
+        let mut starts = Vec::new();
+        let mut code_len = 0;
+        for (_, modes) in instructions.iter() {
+            starts.push(code_len as isize);
+            code_len += modes.len() + 1;
+        }
+        let len = code_len + rng.between(4, 8) as usize;
+
+        let mut program = Vec::with_capacity(len);
+        for (opcode, modes) in instructions.iter_mut() {
+            let writes_last = matches!(*opcode, 1 | 2 | 3 | 7 | 8);
+            let last = modes.len().saturating_sub(1);
+
+            let mut parameters = Vec::new();
+            for (index, mode) in modes.iter_mut().enumerate() {
+                if writes_last && index == last && *mode == MODE_IMM as isize && !rng.one_in(10) {
+                    *mode = MODE_POS as isize;
+                }
+                let is_target = matches!(*opcode, 5 | 6) && index == 1;
+
+                parameters.push(match *mode as u8 {
+                    MODE_IMM if is_target => rng.pick(&starts),
+                    MODE_IMM if rng.one_in(30) => rng.pick(&[isize::MAX / 3, isize::MIN / 3]),
+                    MODE_IMM => rng.between(-5, 5),
+                    MODE_REL => rng.between(-4, len as isize),
+                    _ if rng.one_in(3) => rng.below(len) as isize,
+                    _ => rng.between(code_len as isize, len as isize - 1),
+                });
+            }
+
+            let mode_digits = modes
+                .iter()
+                .rev()
+                .fold(0, |digits, mode| digits * 10 + mode);
+            program.push(*opcode + mode_digits * 100);
+            program.extend(parameters);
+        }
+        while program.len() < len {
+            program.push(match rng.below(10) {
+                0 => rng.between(-5, -1),
+                1 if rng.one_in(3) => isize::MAX / 3,
+                _ => rng.below(len) as isize,
+            });
+        }
+
+        let inputs = if set == OpcodeSet::Day2 {
+            Vec::new()
+        } else {
+            (0..rng.below(4)).map(|_| rng.between(-3, 10)).collect()
+        };
+
+        Self { program, inputs }
+    }
+}
+
+/// Why a run stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ending {
+    Halted,
+    NeedsInput,
+    OutOfSteps,
+    Failed(IntcodeError),
+}
+
+/// Everything observable about one run of a case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub outputs: Vec<isize>,
+    pub ending: Ending,
+    pub memory_len: usize,
+    /// The non-zero cells left in memory, by address
+    pub memory: Option<Vec<(usize, isize)>>,
+    /// The full value of each cell widened past `isize`, by address
+    pub wide: Option<Vec<(usize, BigInt)>>,
+}
+
+impl Outcome {
+    /// Takes memory as its length and non-zero cells, so that paged memory never has
+    /// to be filled in
+    pub(crate) fn new<I, W>(
+        outputs: Vec<isize>,
+        ending: Ending,
+        memory_len: usize,
+        cells: I,
+        wide: W,
+    ) -> Self
+    where
+        I: IntoIterator<Item = (usize, isize)>,
+        W: IntoIterator<Item = (usize, BigInt)>,
+    {
+        let (cells, wide) = match ending {
+            Ending::Failed(_) => (None, None),
+            _ => (
+                Some(cells.into_iter().collect()),
+                Some(wide.into_iter().collect()),
+            ),
+        };
+
+        Self {
+            outputs,
+            ending,
+            memory_len,
+            memory: cells,
+            wide,
+        }
+    }
+
+    /// What a run of `program` left behind
+    fn of(outputs: Vec<isize>, ending: Ending, program: &IntcodeProgram) -> Self {
+        Self::new(
+            outputs,
+            ending,
+            program.memory_len(),
+            program.memory_cells(),
+            program
+                .wide_cells()
+                .map(|(address, value)| (address, value.clone())),
+        )
+    }
+
+    /// Whether two runs behaved the same. How much of a failing instruction takes
+    /// effect isn't specified, so memory is only compared for runs that didn't fail.
+    pub fn agrees_with(&self, other: &Outcome) -> bool {
+        self.outputs == other.outputs
+            && self.ending == other.ending
+            && (matches!(self.ending, Ending::Failed(_))
+                || (self.memory_len == other.memory_len
+                    && self.memory == other.memory
+                    && self.wide == other.wide))
+    }
+}
+
+/// One way of running an Intcode program, as `run(program, inputs, max_steps, policy)`.
+/// It gives `None` for a case whose behaviour it can't follow, which leaves the case
+/// to the other implementations.
+#[derive(Clone, Copy)]
+pub struct Implementation {
+    pub name: &'static str,
+    pub policy: OverflowPolicy,
+    pub run: fn(&[isize], &[isize], u64, OverflowPolicy) -> Option<Outcome>,
+}
+
+impl fmt::Debug for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Implementation({}, {:?})", self.name, self.policy)
+    }
+}
+
+/// Collects outputs until a run stops for any other reason
+fn collect<F>(mut run: F) -> (Vec<isize>, Ending)
+where
+    F: FnMut() -> Result<IntcodeResult, IntcodeError>,
+{
+    let mut outputs = Vec::new();
+    loop {
+        match run() {
+            Ok(IntcodeResult::Suspend(value)) => outputs.push(value),
+            Ok(IntcodeResult::Halt) => return (outputs, Ending::Halted),
+            Ok(IntcodeResult::NeedsInput) => return (outputs, Ending::NeedsInput),
+            Ok(IntcodeResult::BudgetExhausted) => return (outputs, Ending::OutOfSteps),
+            Err(err) => return (outputs, Ending::Failed(err)),
+        }
+    }
+}
+
+/// A program loaded with `ops` and `inputs` that runs for at most `steps`
+fn prepare(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+    kind: MemoryKind,
+) -> IntcodeProgram {
+    let mut program = IntcodeProgram::with_memory(ops.to_vec(), kind);
+    program.extend_inputs(inputs.iter().copied());
+    program.set_step_budget(Some(steps));
+    program.set_overflow_policy(policy);
+    program
+}
+
+fn run_configured<F>(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+    kind: MemoryKind,
+    f: F,
+) -> Option<Outcome>
+where
+    F: FnOnce(&mut IntcodeProgram),
+{
+    let mut program = prepare(ops, inputs, steps, policy, kind);
+    f(&mut program);
+
+    let (outputs, ending) = collect(|| program.run());
+    Some(Outcome::of(outputs, ending, &program))
+}
+
+fn run_dense(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    run_configured(ops, inputs, steps, policy, MemoryKind::Dense, |_| {})
+}
+
+fn run_paged(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    run_configured(ops, inputs, steps, policy, MemoryKind::Paged, |_| {})
+}
+
+fn run_cached(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    run_configured(ops, inputs, steps, policy, MemoryKind::Dense, |program| {
+        program.enable_decode_cache()
+    })
+}
+
+fn run_traced(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    run_configured(ops, inputs, steps, policy, MemoryKind::Dense, |program| {
+        program.set_tracer(Arc::new(Mutex::new(NullTracer)))
+    })
+}
+
+fn run_opcode_table(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    run_configured(ops, inputs, steps, policy, MemoryKind::Dense, |program| {
+        program.set_opcode_table(Arc::new(OpcodeTable::standard()))
+    })
+}
+
+fn run_history(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    let mut history = History::new(prepare(ops, &[], steps, policy, MemoryKind::Dense));
+    history.extend_inputs(inputs.iter().copied());
+
+    let (outputs, ending) = collect(|| history.run());
+    Some(Outcome::of(outputs, ending, history.program()))
+}
+
+/// Runs half the steps, then carries on from a binary snapshot
+fn run_snapshot(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    let mut program = prepare(ops, inputs, steps / 2, policy, MemoryKind::Dense);
+    let (mut outputs, mut ending) = collect(|| program.run());
+
+    if ending == Ending::OutOfSteps {
+        let bytes = program.snapshot().to_bytes();
+        let snapshot = Snapshot::from_bytes(&bytes).expect("snapshot should round trip");
+        let mut resumed = IntcodeProgram::from_snapshot(snapshot).expect("snapshot should restore");
+        resumed.set_step_budget(Some(steps - program.cycles()));
+
+        let (more_outputs, resumed_ending) = collect(|| resumed.run());
+        outputs.extend(more_outputs);
+        ending = resumed_ending;
+        program = resumed;
+    }
+
+    Some(Outcome::of(outputs, ending, &program))
+}
+
+/// How many cells at the end of a program `run_device` hands to its device
+const DEVICE_CELLS: usize = 4;
+
+/// Stands in for the last cells of a program, and takes its input and output
+#[derive(Debug)]
+struct FuzzDevice {
+    mapped: Range<usize>,
+    cells: Vec<isize>,
+    inputs: VecDeque<isize>,
+    outputs: Vec<isize>,
+}
+
+impl IoDevice for FuzzDevice {
+    fn input(&mut self) -> io::Result<Option<isize>> {
+        Ok(self.inputs.pop_front())
+    }
+
+    fn output(&mut self, value: isize) -> io::Result<()> {
+        self.outputs.push(value);
+        Ok(())
+    }
+
+    fn mapped_range(&self) -> Option<Range<usize>> {
+        Some(self.mapped.clone())
+    }
+
+    fn read(&mut self, address: usize) -> io::Result<isize> {
+        Ok(self.cells[address - self.mapped.start])
+    }
+
+    fn write(&mut self, address: usize, value: isize) -> io::Result<()> {
+        self.cells[address - self.mapped.start] = value;
+        Ok(())
+    }
+}
+
+/// Runs with the last cells of the program mapped to a device holding the same values,
+/// which should make no difference to anything but instruction fetches. Those always
+/// come from memory, so a case that runs code from the mapped cells is left out.
+fn run_device(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    let mapped = ops.len().saturating_sub(DEVICE_CELLS)..ops.len();
+    let device = Arc::new(Mutex::new(FuzzDevice {
+        mapped: mapped.clone(),
+        cells: ops[mapped.clone()].to_vec(),
+        inputs: inputs.iter().copied().collect(),
+        outputs: Vec::new(),
+    }));
+    let mut program = prepare(ops, &[], steps, policy, MemoryKind::Dense);
+    program.attach_device(device.clone());
+
+    let ending = loop {
+        let address = program.exec_ptr();
+        let width = Instruction::decode(address, program.peek_value(address))
+            .map_or(1, |instruction| instruction.width());
+        if address < mapped.end && mapped.start < address + width {
+            return None;
+        }
+
+        match program.step() {
+            Ok(None) => {}
+            Ok(Some(IntcodeResult::Halt)) => break Ending::Halted,
+            Ok(Some(IntcodeResult::NeedsInput)) => break Ending::NeedsInput,
+            Ok(Some(IntcodeResult::BudgetExhausted)) => break Ending::OutOfSteps,
+            Ok(Some(IntcodeResult::Suspend(_))) => unreachable!("outputs go to the device"),
+            Err(err) => break Ending::Failed(err),
+        }
+    };
+
+    let device = device.lock().expect("device lock was poisoned");
+    let mut cells: Vec<(usize, isize)> = program
+        .memory_cells()
+        .filter(|(address, _)| !mapped.contains(address))
+        .chain(
+            mapped
+                .clone()
+                .zip(device.cells.iter().copied())
+                .filter(|(_, value)| *value != 0),
+        )
+        .collect();
+    cells.sort_unstable();
+    Some(Outcome::new(
+        device.outputs.clone(),
+        ending,
+        program.memory_len(),
+        cells,
+        Vec::new(),
+    ))
+}
+
+/// A deliberately plain interpreter, written from the puzzle descriptions rather than
+/// sharing any code with `IntcodeProgram`, that keeps memory in a map. It splits
+/// opcodes into operations and modes itself, and only shares `IntcodeError` so that
+/// failures can be compared.
+///
+/// Cells are `i128`, which covers every policy but `OverflowPolicy::Arbitrary` past
+/// that range, where it gives up.
+fn run_reference(
+    ops: &[isize],
+    inputs: &[isize],
+    steps: u64,
+    policy: OverflowPolicy,
+) -> Option<Outcome> {
+    struct Machine {
+        memory: HashMap<usize, i128>,
+        len: usize,
+        exec_ptr: usize,
+        relative_base: isize,
+        policy: OverflowPolicy,
+    }
+
+    /// How far an instruction got
+    enum Step {
+        Ran,
+        NeedsInput,
+        /// The result is too big for an `i128`, under a policy that keeps it anyway
+        TooBig,
+    }
+
+    impl Machine {
+        fn read(&mut self, address: usize) -> i128 {
+            self.len = self.len.max(address + 1);
+            self.memory.get(&address).copied().unwrap_or(0)
+        }
+
+        fn write(&mut self, address: usize, value: i128) {
+            self.len = self.len.max(address + 1);
+            self.memory.insert(address, value);
+        }
+
+        fn overflow(&self) -> IntcodeError {
+            IntcodeError::Overflow {
+                address: self.exec_ptr,
+            }
+        }
+
+        /// Whatever the policy, addresses, offsets, opcodes and outputs have to fit in
+        /// an `isize`
+        fn narrow(&self, value: i128) -> Result<isize, IntcodeError> {
+            isize::try_from(value).map_err(|_| self.overflow())
+        }
+
+        /// Applies the policy to the result of an `add` or `mul`, which is `None` if
+        /// it doesn't fit in an `i128`
+        fn fit(&self, value: Option<i128>) -> Result<Option<i128>, IntcodeError> {
+            match (self.policy, value) {
+                (OverflowPolicy::Error, Some(value)) => self.narrow(value).map(|v| Some(v as i128)),
+                (OverflowPolicy::Wrap, Some(value)) => Ok(Some(value as isize as i128)),
+                (OverflowPolicy::Arbitrary, None) => Ok(None),
+                (_, Some(value)) => Ok(Some(value)),
+                (_, None) => Err(self.overflow()),
+            }
+        }
+
+        fn offset(&self, a: isize, b: isize) -> Result<isize, IntcodeError> {
+            match self.policy {
+                OverflowPolicy::Wrap => Ok(a.wrapping_add(b)),
+                _ => a.checked_add(b).ok_or_else(|| self.overflow()),
+            }
+        }
+
+        fn address(&self, target: isize) -> Result<usize, IntcodeError> {
+            if target < 0 {
+                Err(IntcodeError::NegativeAddress {
+                    address: self.exec_ptr,
+                    target,
+                })
+            } else {
+                Ok(target as usize)
+            }
+        }
+
+        /// The address parameter `index` refers to
+        fn locate(&mut self, opcode: isize, index: usize) -> Result<usize, IntcodeError> {
+            let ptr = self.exec_ptr + 1 + index;
+            let mode = opcode / 10_isize.pow(index as u32 + 2) % 10;
+            match mode {
+                0 => {
+                    let target = self.read(ptr);
+                    let target = self.narrow(target)?;
+                    self.address(target)
+                }
+                1 => Ok(ptr),
+                2 => {
+                    let offset = self.read(ptr);
+                    let offset = self.narrow(offset)?;
+                    let target = self.offset(offset, self.relative_base)?;
+                    self.address(target)
+                }
+                _ => unreachable!("modes are checked before running"),
+            }
+        }
+
+        fn load(&mut self, opcode: isize, index: usize) -> Result<i128, IntcodeError> {
+            let address = self.locate(opcode, index)?;
+            Ok(self.read(address))
+        }
+
+        fn load_narrow(&mut self, opcode: isize, index: usize) -> Result<isize, IntcodeError> {
+            let value = self.load(opcode, index)?;
+            self.narrow(value)
+        }
+
+        fn store_to(&mut self, opcode: isize, index: usize) -> Result<usize, IntcodeError> {
+            if opcode / 10_isize.pow(index as u32 + 2) % 10 == 1 {
+                return Err(IntcodeError::ImmediateWrite {
+                    address: self.exec_ptr,
+                });
+            }
+            self.locate(opcode, index)
+        }
+    }
+
+    let mut machine = Machine {
+        memory: ops
+            .iter()
+            .enumerate()
+            .map(|(address, value)| (address, *value as i128))
+            .collect(),
+        len: ops.len(),
+        exec_ptr: 0,
+        relative_base: 0,
+        policy,
+    };
+    let mut inputs = inputs.iter().copied();
+    let mut outputs = Vec::new();
+    let mut executed = 0;
+
+    let ending = loop {
+        if executed == steps {
+            break Ending::OutOfSteps;
+        }
+        if machine.exec_ptr >= machine.len {
+            break Ending::Failed(IntcodeError::PointerOutOfBounds {
+                address: machine.exec_ptr,
+            });
+        }
+
+        let address = machine.exec_ptr;
+        let opcode = machine.read(address);
+        let opcode = match machine.narrow(opcode) {
+            Ok(opcode) => opcode,
+            Err(err) => break Ending::Failed(err),
+        };
+        let arity = match opcode % 100 {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => break Ending::Failed(IntcodeError::UnknownOpcode { address, opcode }),
+        };
+        if (0..arity).any(|index| opcode / 10_isize.pow(index + 2) % 10 > 2) {
+            break Ending::Failed(IntcodeError::InvalidArgMode { address, opcode });
+        }
+
+        let result: Result<Step, IntcodeError> = (|| {
+            let mut next = address + arity as usize + 1;
+            match opcode % 100 {
+                op @ 1 | op @ 2 | op @ 7 | op @ 8 => {
+                    let a = machine.load(opcode, 0)?;
+                    let b = machine.load(opcode, 1)?;
+                    let dest = machine.store_to(opcode, 2)?;
+                    let value = match op {
+                        1 => machine.fit(a.checked_add(b))?,
+                        2 => machine.fit(a.checked_mul(b))?,
+                        7 => Some((a < b) as i128),
+                        _ => Some((a == b) as i128),
+                    };
+                    match value {
+                        Some(value) => machine.write(dest, value),
+                        None => return Ok(Step::TooBig),
+                    }
+                }
+                3 => {
+                    let dest = machine.store_to(opcode, 0)?;
+                    match inputs.next() {
+                        Some(value) => machine.write(dest, value as i128),
+                        None => return Ok(Step::NeedsInput),
+                    }
+                }
+                4 => outputs.push(machine.load_narrow(opcode, 0)?),
+                op @ 5 | op @ 6 => {
+                    let condition = machine.load(opcode, 0)? != 0;
+                    if condition == (op == 5) {
+                        let target = machine.load_narrow(opcode, 1)?;
+                        next = machine.address(target)?;
+                    }
+                }
+                9 => {
+                    let offset = machine.load_narrow(opcode, 0)?;
+                    machine.relative_base = machine.offset(machine.relative_base, offset)?;
+                }
+                _ => {}
+            }
+            machine.exec_ptr = next;
+            Ok(Step::Ran)
+        })();
+
+        match result {
+            Ok(Step::Ran) => executed += 1,
+            Ok(Step::NeedsInput) => break Ending::NeedsInput,
+            Ok(Step::TooBig) => return None,
+            Err(err) => break Ending::Failed(err),
+        }
+        if opcode % 100 == 99 {
+            break Ending::Halted;
+        }
+    };
+
+    let mut cells: Vec<(usize, i128)> = machine
+        .memory
+        .into_iter()
+        .filter(|(_, value)| *value as isize != 0)
+        .collect();
+    cells.sort_unstable();
+    let wide = cells
+        .iter()
+        .filter(|(_, value)| isize::try_from(*value).is_err())
+        .map(|(address, value)| (*address, BigInt::from(*value)))
+        .collect::<Vec<_>>();
+    Some(Outcome::new(
+        outputs,
+        ending,
+        machine.len,
+        cells
+            .into_iter()
+            .map(|(address, value)| (address, value as isize)),
+        wide,
+    ))
+}
+
+/// Every implementation that can run programs from `set` under `policy`, with the ones
+/// that store memory sparsely first so that programs reaching past `FUZZ_MAX_MEMORY`
+/// are caught before any dense one runs.
+///
+/// The baseline day machines only run under `OverflowPolicy::Wrap`, and each only for
+/// the sets its day covers. A device can't hold a widened value, so `run_device` is
+/// left out of the widening policies.
+pub fn implementations(set: OpcodeSet, policy: OverflowPolicy) -> Vec<Implementation> {
+    type Run = fn(&[isize], &[isize], u64, OverflowPolicy) -> Option<Outcome>;
+    let mut runs: Vec<(&'static str, Run)> = vec![
+        ("reference", run_reference),
+        ("paged", run_paged),
+        ("dense", run_dense),
+        ("decode cache", run_cached),
+        ("traced", run_traced),
+        ("opcode table", run_opcode_table),
+        ("history", run_history),
+        ("snapshot", run_snapshot),
+    ];
+    if !policy.widens() {
+        runs.push(("device", run_device));
+    }
+    if policy == OverflowPolicy::Wrap {
+        if set == OpcodeSet::Day2 {
+            runs.push(("day 2", baseline::run_day2));
+        }
+        if set != OpcodeSet::Day9 {
+            runs.push(("day 5", baseline::run_day5));
+            runs.push(("day 7", baseline::run_day7));
+        }
+        runs.push(("day 9", baseline::run_day9));
+    }
+
+    runs.into_iter()
+        .map(|(name, run)| Implementation { name, policy, run })
+        .collect()
+}
+
+/// A case the implementations disagree on, with what each of them did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub case: Case,
+    pub outcomes: Vec<(&'static str, Outcome)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program: Vec<String> = self.case.program.iter().map(|v| v.to_string()).collect();
+        writeln!(f, "program: {}", program.join(","))?;
+        writeln!(f, "inputs: {:?}", self.case.inputs)?;
+
+        let (_, first) = &self.outcomes[0];
+        for (name, outcome) in self.outcomes.iter() {
+            let marker = if outcome.agrees_with(first) { ' ' } else { '!' };
+            writeln!(f, "{} {:<12} {:?}", marker, name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a case through each implementation, returning their outcomes if any differ.
+/// Cases that reach past `FUZZ_MAX_MEMORY` count as agreeing, and implementations that
+/// can't follow a case are left out.
+pub fn check(implementations: &[Implementation], case: &Case, steps: u64) -> Option<Divergence> {
+    let mut outcomes = Vec::new();
+    for implementation in implementations {
+        let run = implementation.run;
+        let outcome = match run(&case.program, &case.inputs, steps, implementation.policy) {
+            Some(outcome) => outcome,
+            None => continue,
+        };
+        if outcome.memory_len > FUZZ_MAX_MEMORY {
+            return None;
+        }
+        outcomes.push((implementation.name, outcome));
+    }
+
+    if outcomes
+        .iter()
+        .all(|(_, outcome)| outcome.agrees_with(&outcomes[0].1))
+    {
+        None
+    } else {
+        Some(Divergence {
+            case: case.clone(),
+            outcomes,
+        })
+    }
+}
+
+/// Shrinks a diverging case by dropping inputs and cells and by moving values towards
+/// zero, for as long as the implementations still disagree
+pub fn minimize(
+    implementations: &[Implementation],
+    divergence: Divergence,
+    steps: u64,
+) -> Divergence {
+    let mut best = divergence;
+
+    loop {
+        let mut candidates = Vec::new();
+        for index in (0..best.case.inputs.len()).rev() {
+            let mut case = best.case.clone();
+            case.inputs.remove(index);
+            candidates.push(case);
+        }
+        for index in (0..best.case.program.len()).rev() {
+            let mut case = best.case.clone();
+            case.program.remove(index);
+            candidates.push(case);
+        }
+        for (index, value) in best.case.program.iter().enumerate() {
+            for smaller in [0, value / 2] {
+                if smaller != *value {
+                    let mut case = best.case.clone();
+                    case.program[index] = smaller;
+                    candidates.push(case);
+                }
+            }
+        }
+
+        match candidates
+            .iter()
+            .find_map(|case| check(implementations, case, steps))
+        {
+            Some(smaller) => best = smaller,
+            None => return best,
+        }
+    }
+}
+
+/// Checks `runs` generated cases, seeded from `seed` upwards, returning the first
+/// divergence found once minimized
+pub fn fuzz(
+    implementations: &[Implementation],
+    set: OpcodeSet,
+    seed: u64,
+    runs: u64,
+) -> Option<Divergence> {
+    (seed..seed.saturating_add(runs)).find_map(|seed| {
+        let case = Case::generate(seed, set);
+        check(implementations, &case, FUZZ_MAX_STEPS)
+            .map(|divergence| minimize(implementations, divergence, FUZZ_MAX_STEPS))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn implementations_agree() {
+        for policy in FUZZ_POLICIES.iter() {
+            for set in OpcodeSet::ALL.iter() {
+                if let Some(divergence) = fuzz(&implementations(*set, *policy), *set, 0, 300) {
+                    panic!(
+                        "{:?} implementations disagree under {:?}:\n{}",
+                        set, policy, divergence
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn implementations_follow_most_cases() {
+        // An implementation that gave up on everything would agree with everything
+        for policy in FUZZ_POLICIES.iter() {
+            for set in OpcodeSet::ALL.iter() {
+                for implementation in implementations(*set, *policy) {
+                    let followed = (0..100)
+                        .map(|seed| Case::generate(seed, *set))
+                        .filter(|case| {
+                            let run = implementation.run;
+                            run(&case.program, &case.inputs, FUZZ_MAX_STEPS, *policy).is_some()
+                        })
+                        .count();
+                    assert!(
+                        followed >= 25,
+                        "{:?} only followed {} {:?} cases",
+                        implementation,
+                        followed,
+                        set
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn generated_programs_stay_in_their_set() {
+        for seed in 0..50 {
+            let case = Case::generate(seed, OpcodeSet::Day2);
+            assert_eq!(Case::generate(seed, OpcodeSet::Day2), case);
+            assert!(case.inputs.is_empty());
+
+            // The code runs from the start up to the first `hlt`
+            let mut address = 0;
+            loop {
+                let instruction = crate::Instruction::decode(address, case.program[address]);
+                match instruction {
+                    Ok(crate::Instruction::Add(modes)) | Ok(crate::Instruction::Mul(modes)) => {
+                        assert_eq!(modes, [MODE_POS; 3]);
+                        address += 4;
+                    }
+                    Ok(crate::Instruction::Halt) => break,
+                    other => panic!("seed {} generated {:?}", seed, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn divergences_are_minimized() {
+        // Gets multiplication wrong whenever it sees it
+        fn broken(
+            ops: &[isize],
+            inputs: &[isize],
+            steps: u64,
+            policy: OverflowPolicy,
+        ) -> Option<Outcome> {
+            let ops: Vec<isize> = ops
+                .iter()
+                .map(|op| if *op == 1002 { 1001 } else { *op })
+                .collect();
+            run_dense(&ops, inputs, steps, policy)
+        }
+        let implementations = [
+            implementations(OpcodeSet::Day5, OverflowPolicy::Error)[0],
+            Implementation {
+                name: "broken",
+                policy: OverflowPolicy::Error,
+                run: broken,
+            },
+        ];
+
+        let divergence = fuzz(&implementations, OpcodeSet::Day5, 0, 1000).unwrap();
+        assert_eq!(divergence.outcomes.len(), 2);
+        assert!(divergence.case.program.contains(&1002));
+        assert!(divergence.case.program.len() <= 8);
+        assert!(divergence.to_string().contains("! broken"));
+    }
+}
@@ -0,0 +1,442 @@
+use crate::{BigInt, IntcodeError, IntcodeProgram, IntcodeResult};
+use std::collections::{BTreeSet, VecDeque};
+
+/// The changes made by a single instruction, enough to put them back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UndoRecord {
+    pub(crate) exec_ptr: usize,
+    pub(crate) relative_base: isize,
+    pub(crate) halted: bool,
+    pub(crate) memory_len: usize,
+    /// The previous value of each cell written, in the order they were written
+    pub(crate) writes: Vec<(usize, isize)>,
+    /// The previous full value of each cell whose widened value changed, in order
+    pub(crate) wide_writes: Vec<(usize, Option<BigInt>)>,
+    /// The input value consumed, if any
+    pub(crate) input: Option<isize>,
+    /// Whether the input came from a device rather than the queue
+    pub(crate) device_input: bool,
+}
+
+pub const DEFAULT_MAX_RECORDS: usize = 100_000;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000;
+pub const DEFAULT_MAX_CHECKPOINTS: usize = 100;
+
+/// Runs an `IntcodeProgram` while recording enough history to step it backwards.
+///
+/// The most recent steps are kept as an undo log, and a checkpoint is taken every
+/// `checkpoint_interval` steps. Stepping back past the start of the undo log restores
+/// the nearest earlier checkpoint and replays forward from it, so the history reaches
+/// back to the oldest checkpoint kept while memory use stays bounded.
+#[derive(Debug, Clone)]
+pub struct History {
+    program: IntcodeProgram,
+    steps: u64,
+    records: VecDeque<UndoRecord>,
+    /// Untraced copies of the program, so they keep its memory kind
+    checkpoints: VecDeque<(u64, IntcodeProgram)>,
+    /// Every value pushed since the oldest checkpoint, with the step count at the time
+    inputs: Vec<(u64, isize)>,
+
+    max_records: usize,
+    checkpoint_interval: u64,
+    max_checkpoints: usize,
+}
+
+impl History {
+    pub fn new(program: IntcodeProgram) -> Self {
+        Self::with_limits(
+            program,
+            DEFAULT_MAX_RECORDS,
+            DEFAULT_CHECKPOINT_INTERVAL,
+            DEFAULT_MAX_CHECKPOINTS,
+        )
+    }
+
+    /// Creates a history keeping at most `max_records` undo records and
+    /// `max_checkpoints` checkpoints, one taken every `checkpoint_interval` steps
+    pub fn with_limits(
+        program: IntcodeProgram,
+        max_records: usize,
+        checkpoint_interval: u64,
+        max_checkpoints: usize,
+    ) -> Self {
+        assert!(
+            checkpoint_interval > 0,
+            "checkpoint interval must be positive"
+        );
+        assert!(max_checkpoints > 0, "at least one checkpoint must be kept");
+
+        let mut history = Self {
+            program,
+            steps: 0,
+            records: VecDeque::new(),
+            checkpoints: VecDeque::new(),
+            inputs: Vec::new(),
+
+            max_records,
+            checkpoint_interval,
+            max_checkpoints,
+        };
+        history.checkpoints.push_back((0, history.checkpoint()));
+        history
+    }
+
+    pub fn program(&self) -> &IntcodeProgram {
+        &self.program
+    }
+
+    /// The number of instructions executed to reach the current state
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The earliest step that can still be rewound to
+    pub fn earliest_step(&self) -> u64 {
+        let logged = self.steps - self.records.len() as u64;
+        let checkpointed = self.checkpoints.front().map_or(logged, |(step, _)| *step);
+        logged.min(checkpointed)
+    }
+
+    pub fn push_input(&mut self, value: isize) {
+        self.inputs.push((self.steps, value));
+        self.program.push_input(value);
+    }
+
+    pub fn extend_inputs<I: IntoIterator<Item = isize>>(&mut self, values: I) {
+        for value in values {
+            self.push_input(value);
+        }
+    }
+
+    /// Edits memory outside of normal execution. This can't be replayed, so the
+    /// history is discarded and the current state becomes the new starting point.
+    pub fn set_value(&mut self, target_location: usize, new_value: isize) {
+        self.program.set_value(target_location, new_value);
+        self.records.clear();
+        self.checkpoints.clear();
+        self.checkpoints.push_back((self.steps, self.checkpoint()));
+        self.inputs.clear();
+    }
+
+    /// Executes a single instruction, as `IntcodeProgram::step`, recording how to undo it
+    pub fn step(&mut self) -> Result<Option<IntcodeResult>, IntcodeError> {
+        let was_halted = self.program.is_halted();
+        let (result, record) = self.program.step_recorded()?;
+
+        // Waiting for input, sitting on a halt or running out of budget doesn't change
+        // anything
+        let unchanged = matches!(
+            result,
+            Some(IntcodeResult::NeedsInput) | Some(IntcodeResult::BudgetExhausted)
+        );
+        if unchanged || was_halted {
+            return Ok(result);
+        }
+
+        // Input from a device is logged as if it had been pushed just before this step,
+        // so that a replay queues it rather than asking the device again
+        if let (true, Some(value)) = (record.device_input, record.input) {
+            self.inputs.push((self.steps, value));
+        }
+        self.records.push_back(record);
+        if self.records.len() > self.max_records {
+            self.records.pop_front();
+        }
+
+        self.steps += 1;
+        if self.steps.is_multiple_of(self.checkpoint_interval) {
+            self.checkpoints.push_back((self.steps, self.checkpoint()));
+            if self.checkpoints.len() > self.max_checkpoints {
+                self.checkpoints.pop_front();
+                let oldest = self.checkpoints[0].0;
+                self.inputs.retain(|(step, _)| *step >= oldest);
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn run(&mut self) -> Result<IntcodeResult, IntcodeError> {
+        loop {
+            if let Some(result) = self.step()? {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn checkpoint(&self) -> IntcodeProgram {
+        let mut checkpoint = self.program.clone();
+        checkpoint.clear_tracer();
+        checkpoint.detach_device();
+        checkpoint.set_step_budget(None);
+        checkpoint.set_deadline(None);
+        checkpoint
+    }
+
+    /// Re-executes a step that has run before, and so can't fail
+    fn replay_step(&mut self) {
+        let result = self.step().expect("replaying recorded history failed");
+        assert_ne!(
+            result,
+            Some(IntcodeResult::NeedsInput),
+            "replay ran out of input"
+        );
+    }
+
+    /// Rebuilds the undo log for the steps since the latest checkpoint before now,
+    /// returning false if there is no such checkpoint
+    fn replay_from_checkpoint(&mut self) -> bool {
+        let target = self.steps;
+        let (start, checkpoint) = match self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|(step, _)| *step < target)
+        {
+            Some((step, checkpoint)) => (*step, checkpoint.clone()),
+            None => return false,
+        };
+
+        // Replay untraced and without limits, then hand the settings back over
+        let previous = std::mem::replace(&mut self.program, checkpoint);
+
+        self.steps = start;
+        self.records.clear();
+        self.checkpoints.retain(|(step, _)| *step <= start);
+
+        // Inputs pushed after the checkpoint are re-queued at the step they were pushed
+        let inputs = std::mem::take(&mut self.inputs);
+        let mut pending = inputs.iter().filter(|(step, _)| *step >= start).peekable();
+        while self.steps < target {
+            while let Some((_, value)) = pending.next_if(|(step, _)| *step <= self.steps) {
+                self.program.push_input(*value);
+            }
+            self.replay_step();
+        }
+        for (_, value) in pending {
+            self.program.push_input(*value);
+        }
+        self.inputs = inputs;
+        self.program.copy_settings_from(&previous);
+
+        true
+    }
+
+    /// Undoes the most recent step, returning its record
+    fn undo_step(&mut self) -> Option<UndoRecord> {
+        if self.records.is_empty() && !self.replay_from_checkpoint() {
+            return None;
+        }
+
+        let record = self.records.pop_back()?;
+        self.program.undo(&record);
+        self.steps -= 1;
+
+        // Anything recorded past this point belongs to a future that may not happen again
+        let now = self.steps;
+        self.checkpoints.retain(|(step, _)| *step <= now);
+        for (step, _) in self.inputs.iter_mut().rev() {
+            if *step <= now {
+                break;
+            }
+            *step = now;
+        }
+
+        Some(record)
+    }
+
+    /// Undoes the most recent step, returning false if there is no earlier history
+    pub fn reverse_step(&mut self) -> bool {
+        self.undo_step().is_some()
+    }
+
+    /// Rewinds to the state after `step` instructions, returning false if that is out
+    /// of reach, in which case nothing is changed
+    pub fn rewind_to(&mut self, step: u64) -> bool {
+        if step > self.steps || step < self.earliest_step() {
+            return false;
+        }
+        while self.steps > step {
+            self.undo_step();
+        }
+        true
+    }
+
+    /// Steps backwards until the next instruction to execute is on a breakpoint, always
+    /// moving at least one step. Returns false if the start of the history was reached.
+    pub fn reverse_continue(&mut self, breakpoints: &BTreeSet<usize>) -> bool {
+        while self.undo_step().is_some() {
+            if breakpoints.contains(&self.program.exec_ptr()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Rewinds to just before the most recent instruction that wrote to `address`, so
+    /// that it is the next to execute. Nothing is changed if no such write is recorded.
+    pub fn rewind_to_last_write(&mut self, address: usize) -> bool {
+        let start = self.steps;
+        while let Some(record) = self.undo_step() {
+            if record.writes.iter().any(|(written, _)| *written == address) {
+                return true;
+            }
+        }
+
+        // Nothing found, so run forward again to where we started. Every input that was
+        // consumed on the way back is queued again, so execution retraces the same path.
+        let limits = (self.program.step_budget(), self.program.deadline());
+        self.program.set_step_budget(None);
+        self.program.set_deadline(None);
+        while self.steps < start {
+            self.replay_step();
+        }
+        self.program.set_step_budget(limits.0);
+        self.program.set_deadline(limits.1);
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    fn countdown() -> IntcodeProgram {
+        let ops = assemble(
+            "
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    in [n]
+                    out [n]
+                    hlt
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+        IntcodeProgram::new(ops)
+    }
+
+    /// Runs to the end, returning the program state after every step
+    fn record_states(history: &mut History) -> Vec<IntcodeProgram> {
+        let mut states = vec![history.program().clone()];
+        while !history.program().is_halted() {
+            history.step().unwrap();
+            states.push(history.program().clone());
+        }
+        states
+    }
+
+    #[test]
+    fn reverse_steps_restore_every_state() {
+        // A tiny undo log, so most of the way back is replayed from checkpoints
+        for (max_records, interval) in [(3, 4), (100, 1), (1, 100)] {
+            let mut history = History::with_limits(countdown(), max_records, interval, 100);
+            history.extend_inputs(vec![4, 9]);
+            let states = record_states(&mut history);
+            assert_eq!(history.steps() as usize, states.len() - 1);
+
+            for expected in states.iter().rev().skip(1) {
+                assert!(history.reverse_step());
+                assert_eq!(history.program(), expected);
+            }
+            assert!(!history.reverse_step());
+            assert_eq!(history.steps(), 0);
+        }
+    }
+
+    #[test]
+    fn inputs_survive_rewinding() {
+        let mut history = History::with_limits(countdown(), 2, 3, 100);
+        history.push_input(3);
+        assert_eq!(history.run(), Ok(IntcodeResult::Suspend(3)));
+        while history.run() != Ok(IntcodeResult::NeedsInput) {}
+        let waiting_at = history.steps();
+
+        // The second value arrives late, after the program had to wait for it
+        history.push_input(42);
+        assert_eq!(history.run(), Ok(IntcodeResult::Suspend(42)));
+
+        // Rewinding past both reads puts the values back in the queue, in order
+        assert!(history.rewind_to(1));
+        assert_eq!(history.program().pending_input(), &VecDeque::from(vec![42]));
+        assert!(history.rewind_to(0));
+        assert_eq!(
+            history.program().pending_input(),
+            &VecDeque::from(vec![3, 42])
+        );
+
+        let mut outputs = Vec::new();
+        while let IntcodeResult::Suspend(value) = history.run().unwrap() {
+            outputs.push(value);
+        }
+        assert_eq!(outputs, vec![3, 2, 1, 42]);
+
+        assert!(history.rewind_to(waiting_at));
+        assert_eq!(history.program().pending_input(), &VecDeque::from(vec![42]));
+        assert!(!history.rewind_to(history.steps() + 1));
+    }
+
+    #[test]
+    fn reverse_continue_and_last_write() {
+        let mut history = History::with_limits(countdown(), 5, 7, 100);
+        history.extend_inputs(vec![3, 8]);
+        let states = record_states(&mut history);
+
+        // The loop body starts at 2, and was last entered with n = 1
+        let breakpoints: BTreeSet<usize> = vec![2].into_iter().collect();
+        assert!(history.reverse_continue(&breakpoints));
+        assert_eq!(history.program().exec_ptr(), 2);
+        assert_eq!(history.program().peek_value(16), 1);
+        assert_eq!(history.program(), &states[history.steps() as usize]);
+
+        // The last write to n before here was the decrement from 2 to 1
+        assert!(history.rewind_to_last_write(16));
+        assert_eq!(history.program().exec_ptr(), 4);
+        assert_eq!(history.program().peek_value(16), 2);
+
+        // Nothing ever writes to address 1, so the search leaves everything in place
+        let steps = history.steps();
+        assert!(!history.rewind_to_last_write(1));
+        assert_eq!(history.steps(), steps);
+        assert_eq!(history.program(), &states[steps as usize]);
+
+        assert!(!history.reverse_continue(&BTreeSet::new()));
+        assert_eq!(history.steps(), 0);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut history = History::with_limits(countdown(), 2, 3, 2);
+        history.extend_inputs(vec![5, 0]);
+        record_states(&mut history);
+
+        assert_eq!(history.records.len(), 2);
+        assert_eq!(history.checkpoints.len(), 2);
+        let earliest = history.earliest_step();
+        assert!(earliest > 0);
+        assert!(!history.rewind_to(earliest - 1));
+        assert!(history.rewind_to(earliest));
+        assert!(!history.reverse_step());
+    }
+
+    #[test]
+    fn budget_survives_rewinding() {
+        let mut program = countdown();
+        program.set_step_budget(Some(3));
+        let mut history = History::with_limits(program, 1, 2, 100);
+        history.push_input(5);
+        assert_eq!(history.run(), Ok(IntcodeResult::Suspend(5)));
+        assert_eq!(history.run(), Ok(IntcodeResult::BudgetExhausted));
+        assert_eq!(history.steps(), 3);
+
+        // Replaying from a checkpoint ignores the budget, and doesn't refund it
+        assert!(history.rewind_to(0));
+        assert_eq!(history.program().cycles(), 0);
+        assert_eq!(history.program().step_budget(), Some(0));
+        assert_eq!(history.run(), Ok(IntcodeResult::BudgetExhausted));
+    }
+}
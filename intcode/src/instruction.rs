@@ -0,0 +1,205 @@
+use crate::{ArgMode, IntcodeError, MODE_IMM, MODE_POS, MODE_REL};
+
+/// A decoded instruction, with the mode of each parameter stored inline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Add([ArgMode; 3]),
+    Mul([ArgMode; 3]),
+    Input([ArgMode; 1]),
+    Output([ArgMode; 1]),
+    JumpIfTrue([ArgMode; 2]),
+    JumpIfFalse([ArgMode; 2]),
+    LessThan([ArgMode; 3]),
+    Equals([ArgMode; 3]),
+    AdjustBase([ArgMode; 1]),
+    Halt,
+}
+
+/// Reads a parameter mode for each slot of `arg_modes` from the digits above the opcode
+pub(crate) fn fill_modes(
+    address: usize,
+    opcode: isize,
+    arg_modes: &mut [ArgMode],
+) -> Result<(), IntcodeError> {
+    let mut remaining = opcode / 100;
+    for arg_mode in arg_modes.iter_mut() {
+        *arg_mode = match remaining % 10 {
+            0 => MODE_POS,
+            1 => MODE_IMM,
+            2 => MODE_REL,
+            _ => return Err(IntcodeError::InvalidArgMode { address, opcode }),
+        };
+        remaining /= 10;
+    }
+
+    Ok(())
+}
+
+/// Reads `N` parameter modes from the digits above the opcode
+fn read_modes<const N: usize>(address: usize, opcode: isize) -> Result<[ArgMode; N], IntcodeError> {
+    let mut arg_modes = [MODE_POS; N];
+    fill_modes(address, opcode, &mut arg_modes)?;
+    Ok(arg_modes)
+}
+
+impl Instruction {
+    /// Decodes the opcode stored at `address`
+    pub fn decode(address: usize, opcode: isize) -> Result<Self, IntcodeError> {
+        Ok(match opcode % 100 {
+            1 => Instruction::Add(read_modes(address, opcode)?),
+            2 => Instruction::Mul(read_modes(address, opcode)?),
+            3 => Instruction::Input(read_modes(address, opcode)?),
+            4 => Instruction::Output(read_modes(address, opcode)?),
+            5 => Instruction::JumpIfTrue(read_modes(address, opcode)?),
+            6 => Instruction::JumpIfFalse(read_modes(address, opcode)?),
+            7 => Instruction::LessThan(read_modes(address, opcode)?),
+            8 => Instruction::Equals(read_modes(address, opcode)?),
+            9 => Instruction::AdjustBase(read_modes(address, opcode)?),
+            99 => Instruction::Halt,
+            _ => return Err(IntcodeError::UnknownOpcode { address, opcode }),
+        })
+    }
+
+    pub fn opcode(&self) -> usize {
+        match self {
+            Instruction::Add(_) => 1,
+            Instruction::Mul(_) => 2,
+            Instruction::Input(_) => 3,
+            Instruction::Output(_) => 4,
+            Instruction::JumpIfTrue(_) => 5,
+            Instruction::JumpIfFalse(_) => 6,
+            Instruction::LessThan(_) => 7,
+            Instruction::Equals(_) => 8,
+            Instruction::AdjustBase(_) => 9,
+            Instruction::Halt => 99,
+        }
+    }
+
+    /// The mode of each parameter, in order
+    pub fn modes(&self) -> &[ArgMode] {
+        match self {
+            Instruction::Add(modes)
+            | Instruction::Mul(modes)
+            | Instruction::LessThan(modes)
+            | Instruction::Equals(modes) => modes,
+            Instruction::JumpIfTrue(modes) | Instruction::JumpIfFalse(modes) => modes,
+            Instruction::Input(modes)
+            | Instruction::Output(modes)
+            | Instruction::AdjustBase(modes) => modes,
+            Instruction::Halt => &[],
+        }
+    }
+
+    /// The number of cells the instruction takes up, including the opcode
+    pub fn width(&self) -> usize {
+        self.modes().len() + 1
+    }
+}
+
+/// Decoded instructions by address, so that loops skip decoding after their first pass.
+///
+/// An entry only depends on the opcode cell at its own address, so a write to memory
+/// just needs to clear the entry for the cell written. Addresses above
+/// `DECODE_CACHE_LIMIT` are never cached, to keep the cache small for programs that
+/// jump far into paged memory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    entries: Option<Vec<Option<Instruction>>>,
+}
+
+pub const DECODE_CACHE_LIMIT: usize = 1 << 20;
+
+impl DecodeCache {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            entries: if enabled { Some(Vec::new()) } else { None },
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    pub(crate) fn get(&self, address: usize) -> Option<Instruction> {
+        self.entries.as_ref()?.get(address).copied().flatten()
+    }
+
+    pub(crate) fn insert(&mut self, address: usize, instruction: Instruction) {
+        if let Some(entries) = self.entries.as_mut() {
+            if address < DECODE_CACHE_LIMIT {
+                if address >= entries.len() {
+                    entries.resize(address + 1, None);
+                }
+                entries[address] = Some(instruction);
+            }
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        if let Some(entry) = self
+            .entries
+            .as_mut()
+            .and_then(|entries| entries.get_mut(address))
+        {
+            *entry = None;
+        }
+    }
+}
+
+/// The cache is derived from memory, so it never makes two programs differ
+impl PartialEq for DecodeCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DecodeCache {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decoding() {
+        assert_eq!(
+            Instruction::decode(0, 21101),
+            Ok(Instruction::Add([MODE_IMM, MODE_IMM, MODE_REL]))
+        );
+        assert_eq!(
+            Instruction::decode(0, 1005),
+            Ok(Instruction::JumpIfTrue([MODE_POS, MODE_IMM]))
+        );
+        assert_eq!(Instruction::decode(0, 99), Ok(Instruction::Halt));
+        assert_eq!(Instruction::decode(0, 204).map(|i| i.width()), Ok(2));
+        assert_eq!(
+            Instruction::decode(4, 398),
+            Err(IntcodeError::UnknownOpcode {
+                address: 4,
+                opcode: 398
+            })
+        );
+        assert_eq!(
+            Instruction::decode(4, 301),
+            Err(IntcodeError::InvalidArgMode {
+                address: 4,
+                opcode: 301
+            })
+        );
+    }
+
+    #[test]
+    fn cache_invalidation() {
+        let mut cache = DecodeCache::new(true);
+        cache.insert(3, Instruction::Halt);
+        assert_eq!(cache.get(3), Some(Instruction::Halt));
+        cache.invalidate(3);
+        assert_eq!(cache.get(3), None);
+
+        cache.insert(DECODE_CACHE_LIMIT, Instruction::Halt);
+        assert_eq!(cache.get(DECODE_CACHE_LIMIT), None);
+
+        let mut disabled = DecodeCache::new(false);
+        disabled.insert(0, Instruction::Halt);
+        assert_eq!(disabled.get(0), None);
+    }
+}
@@ -0,0 +1,68 @@
+mod ascii;
+mod asm;
+mod baseline;
+mod bigint;
+mod budget;
+mod cfg;
+mod channel;
+mod debugger;
+mod device;
+mod disasm;
+mod error;
+mod fuzz;
+mod history;
+mod instruction;
+mod memory;
+mod network;
+mod opcodes;
+mod pipeline;
+mod profile;
+mod program;
+mod snapshot;
+mod trace;
+
+pub use ascii::{AsciiError, AsciiOutput, AsciiTerminal, Session};
+pub use asm::{assemble, AsmError};
+pub use bigint::BigInt;
+pub use budget::DEADLINE_CHECK_INTERVAL;
+pub use cfg::{BasicBlock, ControlFlowGraph, EdgeKind};
+pub use channel::ThreadedProgram;
+#[cfg(feature = "async")]
+pub use channel::{AsyncProgram, InputSink, OutputStream};
+pub use debugger::Debugger;
+pub use device::{AsciiDevice, FnDevice, IoDevice, NumericStream, QueueDevice, SharedDevice};
+pub use disasm::disassemble;
+pub use error::IntcodeError;
+pub use fuzz::{
+    check, fuzz, implementations, minimize, Case, Divergence, Ending, Implementation, OpcodeSet,
+    Outcome, FUZZ_MAX_MEMORY, FUZZ_MAX_STEPS, FUZZ_POLICIES,
+};
+pub use history::{
+    History, DEFAULT_CHECKPOINT_INTERVAL, DEFAULT_MAX_CHECKPOINTS, DEFAULT_MAX_RECORDS,
+};
+pub use instruction::{Instruction, DECODE_CACHE_LIMIT};
+pub use memory::{MemoryKind, DEFAULT_DENSE_MEMORY_LIMIT, PAGE_SIZE};
+pub use network::{
+    LogEntry, Nat, NetworkError, NetworkStatus, Packet, PacketNetwork, Router, RouterAction,
+    Source, NAT_ADDRESS, NAT_IDLE_ROUNDS,
+};
+pub use opcodes::{Flow, Machine, OpcodeHandler, OpcodeTable};
+pub use pipeline::{NodeId, NodeReport, Pipeline, PipelineError, PipelineReport, PipelineStatus};
+pub use profile::{Profile, REPORT_TOP};
+pub use program::{
+    parse_op, ArgMode, IntcodeProgram, IntcodeResult, Outputs, OverflowPolicy, MODE_IMM, MODE_POS,
+    MODE_REL,
+};
+pub use snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION};
+pub use trace::{JsonLinesTracer, NullTracer, RecordingTracer, SharedTracer, TraceEvent, Tracer};
+
+/// Parses a comma separated Intcode listing, as given in the puzzle inputs
+pub fn read_program(program_str: &str) -> IntcodeProgram {
+    IntcodeProgram::new(
+        program_str
+            .trim()
+            .split(',')
+            .map(|token| token.parse::<isize>().expect("Could not parse input token"))
+            .collect(),
+    )
+}
@@ -0,0 +1,309 @@
+use std::{borrow::Cow, collections::HashMap};
+
+/// The number of cells in each page of `MemoryKind::Paged` memory
+pub const PAGE_SIZE: usize = 1024;
+
+/// How many cells `MemoryKind::Dense` memory may grow to, unless the program is given
+/// another limit. Dense memory allocates every cell below the highest address touched,
+/// so one stray address could otherwise exhaust the process's memory.
+pub const DEFAULT_DENSE_MEMORY_LIMIT: usize = 1 << 24;
+
+/// How a program stores its memory.
+///
+/// Both kinds behave identically, they only differ in cost. `Dense` keeps every cell
+/// up to the highest address touched in one `Vec`, which is fastest for the usual
+/// compact programs. `Paged` only allocates the pages that hold a non-zero value, so a
+/// program can touch huge addresses without allocating everything below them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryKind {
+    #[default]
+    Dense,
+    Paged,
+}
+
+impl MemoryKind {
+    /// The limit programs start with. Paged memory only allocates the pages written
+    /// to, so it has none.
+    pub fn default_limit(self) -> Option<usize> {
+        match self {
+            MemoryKind::Dense => Some(DEFAULT_DENSE_MEMORY_LIMIT),
+            MemoryKind::Paged => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PagedMemory {
+    pages: HashMap<usize, Box<[isize; PAGE_SIZE]>>,
+    len: usize,
+}
+
+impl PagedMemory {
+    fn get(&self, address: usize) -> isize {
+        self.pages
+            .get(&(address / PAGE_SIZE))
+            .map_or(0, |page| page[address % PAGE_SIZE])
+    }
+
+    fn set(&mut self, address: usize, value: isize) {
+        let page_number = address / PAGE_SIZE;
+        if value == 0 && !self.pages.contains_key(&page_number) {
+            // Missing pages already read as zero
+            return;
+        }
+
+        let page = self
+            .pages
+            .entry(page_number)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        page[address % PAGE_SIZE] = value;
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        self.pages
+            .retain(|page_number, _| page_number * PAGE_SIZE < len);
+        if let Some(page) = self.pages.get_mut(&(len / PAGE_SIZE)) {
+            for cell in page[len % PAGE_SIZE..].iter_mut() {
+                *cell = 0;
+            }
+        }
+        self.len = len;
+    }
+
+    /// Every non-zero cell, in address order, visiting only the pages that exist
+    fn cells(&self) -> impl Iterator<Item = (usize, isize)> + '_ {
+        let mut page_numbers: Vec<usize> = self.pages.keys().copied().collect();
+        page_numbers.sort_unstable();
+
+        page_numbers.into_iter().flat_map(move |page_number| {
+            let start = page_number * PAGE_SIZE;
+            self.pages[&page_number]
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| **value != 0)
+                .map(move |(offset, value)| (start + offset, *value))
+        })
+    }
+}
+
+/// A program's memory, in whichever `MemoryKind` it was created with.
+///
+/// Memory has a length, which grows to cover every address read or written, so that
+/// the exec pointer can run off the end of it the same way for either kind.
+#[derive(Debug, Clone)]
+pub(crate) enum Memory {
+    Dense(Vec<isize>),
+    Paged(PagedMemory),
+}
+
+impl Memory {
+    pub(crate) fn new(ops: Vec<isize>, kind: MemoryKind) -> Self {
+        match kind {
+            MemoryKind::Dense => Memory::Dense(ops),
+            MemoryKind::Paged => {
+                let mut memory = PagedMemory {
+                    pages: HashMap::new(),
+                    len: ops.len(),
+                };
+                for (address, value) in ops.into_iter().enumerate() {
+                    memory.set(address, value);
+                }
+                Memory::Paged(memory)
+            }
+        }
+    }
+
+    /// Builds memory of length `len` from the cells that aren't zero. Fails with the
+    /// first address past `limit` instead of allocating up to it.
+    pub(crate) fn from_cells<I: IntoIterator<Item = (usize, isize)>>(
+        len: usize,
+        cells: I,
+        kind: MemoryKind,
+        limit: Option<usize>,
+    ) -> Result<Self, usize> {
+        let limit = limit.unwrap_or(usize::MAX);
+        if len > limit {
+            return Err(limit);
+        }
+
+        let mut memory = match kind {
+            MemoryKind::Dense => Memory::Dense(vec![0; len]),
+            MemoryKind::Paged => Memory::Paged(PagedMemory {
+                pages: HashMap::new(),
+                len,
+            }),
+        };
+        for (address, value) in cells {
+            if address >= limit {
+                return Err(address);
+            }
+            memory.grow(address);
+            memory.set(address, value);
+        }
+        Ok(memory)
+    }
+
+    pub(crate) fn kind(&self) -> MemoryKind {
+        match self {
+            Memory::Dense(_) => MemoryKind::Dense,
+            Memory::Paged(_) => MemoryKind::Paged,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Memory::Dense(ops) => ops.len(),
+            Memory::Paged(memory) => memory.len,
+        }
+    }
+
+    /// Reads a cell, treating anything past the end as zero
+    pub(crate) fn get(&self, address: usize) -> isize {
+        match self {
+            Memory::Dense(ops) => ops.get(address).copied().unwrap_or(0),
+            Memory::Paged(memory) => memory.get(address),
+        }
+    }
+
+    /// Extends memory with zeros so that it covers `address`
+    pub(crate) fn grow(&mut self, address: usize) {
+        if address < self.len() {
+            return;
+        }
+
+        match self {
+            Memory::Dense(ops) => ops.resize(address + 1, 0),
+            Memory::Paged(memory) => memory.len = address + 1,
+        }
+    }
+
+    /// Writes a cell, which must already be covered by `grow`
+    pub(crate) fn set(&mut self, address: usize, value: isize) {
+        match self {
+            Memory::Dense(ops) => ops[address] = value,
+            Memory::Paged(memory) => memory.set(address, value),
+        }
+    }
+
+    /// Shrinks memory back down to `len` cells
+    pub(crate) fn truncate(&mut self, len: usize) {
+        match self {
+            Memory::Dense(ops) => ops.truncate(len),
+            Memory::Paged(memory) => memory.truncate(len),
+        }
+    }
+
+    /// The whole of memory as one slice. Paged memory has to fill in every missing
+    /// page, so anything that may see paged memory should use `cells` instead.
+    pub(crate) fn as_slice(&self) -> Cow<'_, [isize]> {
+        match self {
+            Memory::Dense(ops) => Cow::Borrowed(ops),
+            Memory::Paged(memory) => {
+                let mut ops = vec![0; memory.len];
+                for (address, value) in memory.cells() {
+                    ops[address] = value;
+                }
+                Cow::Owned(ops)
+            }
+        }
+    }
+
+    /// The cells from `start`, up to `count` of them but stopping at the end of memory
+    pub(crate) fn window(&self, start: usize, count: usize) -> Vec<isize> {
+        (start..self.len().min(start + count))
+            .map(|address| self.get(address))
+            .collect()
+    }
+
+    /// Every non-zero cell, in address order. Unlike `as_slice` this never allocates
+    /// the gaps in paged memory.
+    pub(crate) fn cells(&self) -> Box<dyn Iterator<Item = (usize, isize)> + '_> {
+        match self {
+            Memory::Dense(ops) => Box::new(
+                ops.iter()
+                    .copied()
+                    .enumerate()
+                    .filter(|(_, value)| *value != 0),
+            ),
+            Memory::Paged(memory) => Box::new(memory.cells()),
+        }
+    }
+}
+
+/// Memory compares by contents, whichever kind holds it
+impl PartialEq for Memory {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Memory::Dense(a), Memory::Dense(b)) => a == b,
+            _ => self.len() == other.len() && self.cells().eq(other.cells()),
+        }
+    }
+}
+
+impl Eq for Memory {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kinds_agree() {
+        let ops = vec![1, 0, 0, 5, -3];
+        let mut dense = Memory::new(ops.clone(), MemoryKind::Dense);
+        let mut paged = Memory::new(ops, MemoryKind::Paged);
+        assert_eq!(dense, paged);
+
+        for memory in [&mut dense, &mut paged] {
+            memory.grow(3000);
+            memory.set(3000, 7);
+            memory.set(2000, 0);
+            memory.set(0, 0);
+        }
+        assert_eq!(dense, paged);
+        assert_eq!(paged.len(), 3001);
+        assert_eq!(paged.get(3000), 7);
+        assert_eq!(paged.get(5000), 0);
+        assert_eq!(dense.as_slice(), paged.as_slice());
+
+        for memory in [&mut dense, &mut paged] {
+            memory.truncate(4);
+        }
+        assert_eq!(dense, paged);
+        assert_eq!(paged.as_slice(), Cow::Borrowed(&[0, 0, 0, 5][..]));
+        assert_eq!(paged.window(2, 10), vec![0, 5]);
+    }
+
+    #[test]
+    fn paged_memory_stays_sparse() {
+        let mut memory = Memory::new(vec![0; 10], MemoryKind::Paged);
+        memory.grow(1_000_000_000);
+        memory.set(1_000_000_000, 1);
+        memory.set(5, 0);
+
+        match &memory {
+            Memory::Paged(paged) => assert_eq!(paged.pages.len(), 1),
+            Memory::Dense(_) => unreachable!(),
+        }
+        assert_eq!(memory.len(), 1_000_000_001);
+        assert_eq!(memory.get(1_000_000_000), 1);
+        assert_eq!(memory.cells().collect::<Vec<_>>(), vec![(1_000_000_000, 1)]);
+
+        let rebuilt = Memory::from_cells(memory.len(), memory.cells(), MemoryKind::Paged, None);
+        assert_eq!(rebuilt, Ok(memory.clone()));
+
+        // A limit stops dense memory before it allocates
+        let limit = Some(1_000_000);
+        assert_eq!(
+            Memory::from_cells(memory.len(), memory.cells(), MemoryKind::Dense, limit),
+            Err(1_000_000)
+        );
+        assert_eq!(
+            Memory::from_cells(10, vec![(20, 1)], MemoryKind::Dense, Some(16)),
+            Err(20)
+        );
+    }
+}
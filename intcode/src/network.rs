@@ -0,0 +1,448 @@
+use crate::{IntcodeError, IntcodeProgram, IntcodeResult};
+use std::{collections::VecDeque, convert::TryFrom, error::Error, fmt};
+
+/// The address the NAT listens on
+pub const NAT_ADDRESS: isize = 255;
+/// Rounds without any traffic before the NAT decides the network is idle
+pub const NAT_IDLE_ROUNDS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub dest: isize,
+    pub x: isize,
+    pub y: isize,
+}
+
+/// Where a logged packet came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Node(usize),
+    Router,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The round the packet was sent in, counting from 1
+    pub round: usize,
+    pub source: Source,
+    pub packet: Packet,
+}
+
+/// What the router wants done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouterAction {
+    Continue,
+    /// Delivers packets to nodes in the network. Packets for addresses outside it are
+    /// logged and dropped.
+    Inject(Vec<Packet>),
+    /// Ends the simulation
+    Stop,
+}
+
+/// Handles the traffic leaving a `PacketNetwork`, and gets to act at the end of every
+/// round
+pub trait Router {
+    /// A packet sent to an address outside the network
+    fn receive(&mut self, packet: Packet) -> RouterAction;
+
+    /// Called after each round with the number of rounds in a row in which no packets
+    /// were sent and every node found its queue empty
+    fn tick(&mut self, idle_rounds: usize) -> RouterAction;
+}
+
+/// Keeps the last packet sent to `NAT_ADDRESS`, and sends it on to node 0 whenever the
+/// network has been idle for `NAT_IDLE_ROUNDS`. Stops the network when it would send
+/// the same `y` twice in a row.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nat {
+    pub last: Option<Packet>,
+    /// The `y` of each packet sent to node 0
+    pub wakeups: Vec<isize>,
+    /// The `y` it would have sent twice in a row, once the network has stopped
+    pub repeated: Option<isize>,
+}
+
+impl Nat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Router for Nat {
+    fn receive(&mut self, packet: Packet) -> RouterAction {
+        if packet.dest == NAT_ADDRESS {
+            self.last = Some(packet);
+        }
+        RouterAction::Continue
+    }
+
+    fn tick(&mut self, idle_rounds: usize) -> RouterAction {
+        match self.last {
+            Some(packet) if idle_rounds >= NAT_IDLE_ROUNDS => {
+                if self.wakeups.last() == Some(&packet.y) {
+                    self.repeated = Some(packet.y);
+                    return RouterAction::Stop;
+                }
+                self.wakeups.push(packet.y);
+                RouterAction::Inject(vec![Packet { dest: 0, ..packet }])
+            }
+            _ => RouterAction::Continue,
+        }
+    }
+}
+
+/// How a simulation ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkStatus {
+    /// The router asked to stop
+    Stopped,
+    AllHalted,
+    /// The round limit given to `run` was reached
+    RoundLimit,
+    /// A node ran out of its step budget or deadline. The next round picks up with that
+    /// node, once it has been given more with `program_mut`.
+    BudgetExhausted(usize),
+}
+
+/// A node's program failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkError {
+    pub address: usize,
+    pub error: IntcodeError,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node {} failed: {}", self.address, self.error)
+    }
+}
+
+impl Error for NetworkError {}
+
+#[derive(Debug, Clone)]
+struct NetworkNode {
+    program: IntcodeProgram,
+    queue: VecDeque<Packet>,
+    /// Outputs that don't make up a whole packet yet
+    partial: Vec<isize>,
+}
+
+/// Copies of a program, each given its address as its first input, that send each
+/// other packets as `dest, x, y` outputs and read `x, y` pairs, or `-1` when nothing
+/// has arrived.
+///
+/// Nodes run in address order, each once per round until it next wants input, so a
+/// simulation always plays out the same way. Packets to addresses outside the network
+/// go to the router.
+#[derive(Debug, Clone)]
+pub struct PacketNetwork<R> {
+    nodes: Vec<NetworkNode>,
+    router: R,
+    log: Vec<LogEntry>,
+    round: usize,
+    idle_rounds: usize,
+    /// The node to carry on from when a round was cut short, or 0 between rounds
+    next_node: usize,
+    /// Whether `next_node` ran out of budget, and so already has this round's input
+    has_input: bool,
+    /// Whether the round in progress has seen no traffic so far
+    idle: bool,
+}
+
+impl<R: Router> PacketNetwork<R> {
+    pub fn new(program: &IntcodeProgram, size: usize, router: R) -> Self {
+        let nodes = (0..size)
+            .map(|address| {
+                let mut program = program.clone();
+                program.push_input(address as isize);
+                NetworkNode {
+                    program,
+                    queue: VecDeque::new(),
+                    partial: Vec::new(),
+                }
+            })
+            .collect();
+
+        Self {
+            nodes,
+            router,
+            log: Vec::new(),
+            round: 0,
+            idle_rounds: 0,
+            next_node: 0,
+            has_input: false,
+            idle: true,
+        }
+    }
+
+    /// Every packet sent so far, in the order they were sent
+    pub fn log(&self) -> &[LogEntry] {
+        &self.log
+    }
+
+    pub fn router(&self) -> &R {
+        &self.router
+    }
+
+    pub fn into_router(self) -> R {
+        self.router
+    }
+
+    /// The number of rounds completed so far
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    pub fn program(&self, address: usize) -> &IntcodeProgram {
+        &self.nodes[address].program
+    }
+
+    /// A node's program, for instance to raise its budget after
+    /// `NetworkStatus::BudgetExhausted`
+    pub fn program_mut(&mut self, address: usize) -> &mut IntcodeProgram {
+        &mut self.nodes[address].program
+    }
+
+    /// Logs a packet and queues it for its node, returning it if the address is outside
+    /// the network
+    fn send(&mut self, source: Source, packet: Packet) -> Option<Packet> {
+        self.log.push(LogEntry {
+            round: self.round + 1,
+            source,
+            packet,
+        });
+        match usize::try_from(packet.dest) {
+            Ok(dest) if dest < self.nodes.len() => {
+                self.nodes[dest].queue.push_back(packet);
+                None
+            }
+            _ => Some(packet),
+        }
+    }
+
+    /// Returns `false` if the router asked to stop
+    fn apply(&mut self, action: RouterAction) -> bool {
+        match action {
+            RouterAction::Continue => true,
+            RouterAction::Inject(packets) => {
+                for packet in packets {
+                    self.send(Source::Router, packet);
+                }
+                true
+            }
+            RouterAction::Stop => false,
+        }
+    }
+
+    /// Gives every node that hasn't halted its queued packets, or `-1`, and runs it.
+    /// Returns early if the router stops the network or a node runs out of budget, in
+    /// which case the next call finishes the same round, so the simulation plays out
+    /// as if it had never stopped.
+    pub fn run_round(&mut self) -> Result<Option<NetworkStatus>, NetworkError> {
+        if self.next_node == 0 && !self.has_input {
+            self.idle = true;
+        }
+
+        for address in self.next_node..self.nodes.len() {
+            let node = &mut self.nodes[address];
+            if node.program.is_halted() {
+                continue;
+            }
+
+            if self.has_input {
+                self.has_input = false;
+            } else if node.queue.is_empty() {
+                node.program.push_input(-1);
+            } else {
+                self.idle = false;
+                for packet in node.queue.drain(..) {
+                    node.program.extend_inputs([packet.x, packet.y]);
+                }
+            }
+
+            let (outputs, result) = node
+                .program
+                .run_until_input()
+                .map_err(|error| NetworkError { address, error })?;
+            node.partial.extend(outputs);
+
+            let whole = node.partial.len() - node.partial.len() % 3;
+            let sent: Vec<isize> = node.partial.drain(..whole).collect();
+            for chunk in sent.chunks(3) {
+                self.idle = false;
+                let packet = Packet {
+                    dest: chunk[0],
+                    x: chunk[1],
+                    y: chunk[2],
+                };
+                if let Some(packet) = self.send(Source::Node(address), packet) {
+                    let action = self.router.receive(packet);
+                    if !self.apply(action) {
+                        self.next_node = address + 1;
+                        return Ok(Some(NetworkStatus::Stopped));
+                    }
+                }
+            }
+
+            if result == IntcodeResult::BudgetExhausted {
+                self.next_node = address;
+                self.has_input = true;
+                return Ok(Some(NetworkStatus::BudgetExhausted(address)));
+            }
+        }
+
+        self.next_node = 0;
+        self.idle_rounds = if self.idle { self.idle_rounds + 1 } else { 0 };
+        let action = self.router.tick(self.idle_rounds);
+        let stopped = !self.apply(action);
+        self.round += 1;
+        if stopped {
+            return Ok(Some(NetworkStatus::Stopped));
+        }
+        if self.nodes.iter().all(|node| node.program.is_halted()) {
+            return Ok(Some(NetworkStatus::AllHalted));
+        }
+        Ok(None)
+    }
+
+    /// Runs rounds until the simulation ends, or `max_rounds` more have run
+    pub fn run(&mut self, max_rounds: usize) -> Result<NetworkStatus, NetworkError> {
+        for _ in 0..max_rounds {
+            if let Some(status) = self.run_round()? {
+                return Ok(status);
+            }
+        }
+        Ok(NetworkStatus::RoundLimit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    /// Three nodes in a chain. Node 0 starts a packet off on boot, and each node passes
+    /// the packets it gets to the next address, counting the hops in `x`. The last node
+    /// sends them to the NAT.
+    fn relay() -> IntcodeProgram {
+        IntcodeProgram::new(
+            assemble(
+                "
+                        in [a]
+                        jnz [a], #loop
+                        out #1
+                        out #0
+                        out #42
+                loop:   in [x]
+                        eq [x], #-1, [t]
+                        jnz [t], #loop
+                        in [y]
+                        add [a], #1, [d]
+                        eq [d], #3, [t]
+                        jz [t], #send
+                        add #255, #0, [d]
+                send:   out [d]
+                        add [x], #1, [x]
+                        out [x]
+                        out [y]
+                        jz #0, #loop
+                a:      .data 0
+                x:      .data 0
+                y:      .data 0
+                d:      .data 0
+                t:      .data 0
+                ",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn nat_wakes_idle_network() {
+        let mut network = PacketNetwork::new(&relay(), 3, Nat::new());
+        assert_eq!(network.run(100).unwrap(), NetworkStatus::Stopped);
+
+        let nat = network.router();
+        assert_eq!(nat.wakeups, vec![42]);
+        assert_eq!(nat.repeated, Some(42));
+
+        let first_to_nat = network
+            .log()
+            .iter()
+            .find(|entry| entry.packet.dest == NAT_ADDRESS)
+            .unwrap();
+        assert_eq!(
+            *first_to_nat,
+            LogEntry {
+                round: 1,
+                source: Source::Node(2),
+                packet: Packet {
+                    dest: NAT_ADDRESS,
+                    x: 2,
+                    y: 42
+                }
+            }
+        );
+
+        // Two idle rounds pass before the NAT wakes node 0
+        let from_router: Vec<&LogEntry> = network
+            .log()
+            .iter()
+            .filter(|entry| entry.source == Source::Router)
+            .collect();
+        assert_eq!(from_router.len(), 1);
+        assert_eq!(from_router[0].round, 3);
+        assert_eq!(network.log().len(), 7);
+        assert_eq!(network.log()[6].packet.x, 5);
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut first = PacketNetwork::new(&relay(), 3, Nat::new());
+        let mut second = PacketNetwork::new(&relay(), 3, Nat::new());
+        first.run(100).unwrap();
+        second.run(100).unwrap();
+        assert_eq!(first.log(), second.log());
+        assert_eq!(first.round(), second.round());
+    }
+
+    #[test]
+    fn resumes_after_budget() {
+        let mut straight = PacketNetwork::new(&relay(), 3, Nat::new());
+        assert_eq!(straight.run(100).unwrap(), NetworkStatus::Stopped);
+
+        // Every node runs out of budget partway through most of its turns
+        let mut program = relay();
+        program.set_step_budget(Some(3));
+        let mut interrupted = PacketNetwork::new(&program, 3, Nat::new());
+        let mut interruptions = 0;
+        let status = loop {
+            match interrupted.run_round().unwrap() {
+                Some(NetworkStatus::BudgetExhausted(address)) => {
+                    interruptions += 1;
+                    interrupted.program_mut(address).set_step_budget(Some(3));
+                }
+                Some(status) => break status,
+                None => {}
+            }
+        };
+
+        assert!(interruptions > 10);
+        assert_eq!(status, NetworkStatus::Stopped);
+        assert_eq!(interrupted.log(), straight.log());
+        assert_eq!(interrupted.round(), straight.round());
+        assert_eq!(interrupted.router(), straight.router());
+    }
+
+    #[test]
+    fn round_limit_and_halting() {
+        let mut network = PacketNetwork::new(&relay(), 3, Nat::new());
+        assert_eq!(network.run(2).unwrap(), NetworkStatus::RoundLimit);
+        assert_eq!(network.round(), 2);
+
+        let halts = IntcodeProgram::new(vec![3, 0, 99]);
+        let mut network = PacketNetwork::new(&halts, 2, Nat::new());
+        assert_eq!(network.run(10).unwrap(), NetworkStatus::AllHalted);
+        assert!(network.log().is_empty());
+    }
+}
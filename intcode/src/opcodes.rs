@@ -0,0 +1,464 @@
+use crate::{
+    asm::MNEMONICS, instruction::fill_modes, ArgMode, BigInt, IntcodeError, IntcodeProgram,
+    MODE_POS,
+};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Where execution goes after an instruction handled by an `OpcodeHandler`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// On to the instruction after this one
+    Next,
+    /// To the given address, which fails if it is negative
+    Jump(isize),
+    Halt,
+    /// Pause until more input is pushed, then run this instruction again
+    WaitForInput,
+}
+
+/// The semantics of a single opcode, for use in an `OpcodeTable`
+pub trait OpcodeHandler: Send + Sync {
+    fn mnemonic(&self) -> &str;
+
+    /// The number of parameters following the opcode
+    fn arity(&self) -> usize;
+
+    fn execute(&self, machine: &mut Machine<'_>) -> Result<Flow, IntcodeError>;
+}
+
+/// The view of a program given to an `OpcodeHandler` while it executes an instruction.
+///
+/// Parameters are numbered from zero and read in the modes given by the opcode, with
+/// the same tracing, profiling and undo recording as the built in instructions.
+pub struct Machine<'a> {
+    program: &'a mut IntcodeProgram,
+    arg_modes: &'a [ArgMode],
+}
+
+impl<'a> Machine<'a> {
+    pub(crate) fn new(program: &'a mut IntcodeProgram, arg_modes: &'a [ArgMode]) -> Self {
+        Self { program, arg_modes }
+    }
+
+    /// The address of the instruction being executed
+    pub fn address(&self) -> usize {
+        self.program.exec_ptr()
+    }
+
+    pub fn arg_modes(&self) -> &[ArgMode] {
+        self.arg_modes
+    }
+
+    fn param_address(&self, index: usize) -> usize {
+        assert!(
+            index < self.arg_modes.len(),
+            "parameter {} is past the declared arity of {}",
+            index,
+            self.arg_modes.len()
+        );
+        self.address() + 1 + index
+    }
+
+    /// Reads the value of parameter `index`. A value widened past `isize` can't be read
+    /// this way, and gives `IntcodeError::Overflow`.
+    pub fn arg(&mut self, index: usize) -> Result<isize, IntcodeError> {
+        let ptr = self.param_address(index);
+        self.program.get_arg(ptr, self.arg_modes[index])
+    }
+
+    /// Reads the full value of parameter `index`, including one widened past `isize`
+    pub fn wide_arg(&mut self, index: usize) -> Result<BigInt, IntcodeError> {
+        let ptr = self.param_address(index);
+        self.program.get_wide_arg(ptr, self.arg_modes[index])
+    }
+
+    /// Whether parameter `index` is non-zero, which works on widened values too
+    pub fn condition(&mut self, index: usize) -> Result<bool, IntcodeError> {
+        let ptr = self.param_address(index);
+        self.program.get_condition(ptr, self.arg_modes[index])
+    }
+
+    /// Resolves the address parameter `index` writes to
+    pub fn dest(&mut self, index: usize) -> Result<usize, IntcodeError> {
+        let ptr = self.param_address(index);
+        self.program.get_dest_address(ptr, self.arg_modes[index])
+    }
+
+    pub fn write(&mut self, address: usize, value: isize) -> Result<(), IntcodeError> {
+        self.program.store(address, value)
+    }
+
+    /// Writes a value that may not fit in a cell, under the program's overflow policy.
+    /// The widening policies keep it in full, `OverflowPolicy::Wrap` wraps it and
+    /// `OverflowPolicy::Error` fails.
+    pub fn write_wide(&mut self, address: usize, value: BigInt) -> Result<(), IntcodeError> {
+        self.program.store_wide(address, value)
+    }
+
+    /// Reads a memory cell directly, without it counting as a parameter
+    pub fn peek(&self, address: usize) -> isize {
+        self.program.peek_value(address)
+    }
+
+    /// Adds two `isize` values, wrapping under `OverflowPolicy::Wrap` and otherwise
+    /// failing if the sum doesn't fit. To widen results, use `wide_arg` and
+    /// `write_wide` instead.
+    pub fn add(&self, a: isize, b: isize) -> Result<isize, IntcodeError> {
+        self.program.add(a, b)
+    }
+
+    /// Multiplies two `isize` values, wrapping under `OverflowPolicy::Wrap` and
+    /// otherwise failing if the product doesn't fit. To widen results, use `wide_arg`
+    /// and `write_wide` instead.
+    pub fn mul(&self, a: isize, b: isize) -> Result<isize, IntcodeError> {
+        self.program.mul(a, b)
+    }
+
+    /// Takes the next input value. When there is none, the handler should return
+    /// `Flow::WaitForInput` without having changed anything.
+    pub fn input(&mut self) -> Result<Option<isize>, IntcodeError> {
+        self.program.take_input()
+    }
+
+    /// Outputs a value. Only one value can be output per instruction, and a second
+    /// replaces the first.
+    pub fn output(&mut self, value: isize) -> Result<(), IntcodeError> {
+        self.program.emit_output(value)
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.program.relative_base()
+    }
+
+    pub fn adjust_relative_base(&mut self, offset: isize) -> Result<(), IntcodeError> {
+        self.program.adjust_relative_base(offset)
+    }
+}
+
+/// A standard instruction, expressed as a handler
+struct Builtin(usize);
+
+impl OpcodeHandler for Builtin {
+    fn mnemonic(&self) -> &str {
+        MNEMONICS
+            .iter()
+            .find(|(op, _)| *op == self.0)
+            .map_or("?", |(_, mnemonic)| *mnemonic)
+    }
+
+    fn arity(&self) -> usize {
+        match self.0 {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            _ => 0,
+        }
+    }
+
+    fn execute(&self, machine: &mut Machine<'_>) -> Result<Flow, IntcodeError> {
+        match self.0 {
+            op @ 1 | op @ 2 | op @ 7 | op @ 8 => {
+                // The same as the built in instructions decide, so the two agree
+                if let Some(wide_op) = machine.program.wide_operation(op) {
+                    let a = machine.wide_arg(0)?;
+                    let b = machine.wide_arg(1)?;
+                    let dest = machine.dest(2)?;
+                    machine.write_wide(dest, wide_op(&a, &b))?;
+                    return Ok(Flow::Next);
+                }
+                let a = machine.arg(0)?;
+                let b = machine.arg(1)?;
+                let dest = machine.dest(2)?;
+                let result = match op {
+                    1 => machine.add(a, b)?,
+                    2 => machine.mul(a, b)?,
+                    7 => (a < b) as isize,
+                    _ => (a == b) as isize,
+                };
+                machine.write(dest, result)?;
+            }
+            3 => {
+                let dest = machine.dest(0)?;
+                match machine.input()? {
+                    Some(value) => machine.write(dest, value)?,
+                    None => return Ok(Flow::WaitForInput),
+                }
+            }
+            4 => {
+                let value = machine.arg(0)?;
+                machine.output(value)?;
+            }
+            op @ 5 | op @ 6 => {
+                let condition = machine.condition(0)?;
+                if condition == (op == 5) {
+                    return Ok(Flow::Jump(machine.arg(1)?));
+                }
+            }
+            9 => {
+                let offset = machine.arg(0)?;
+                machine.adjust_relative_base(offset)?;
+            }
+            _ => return Ok(Flow::Halt),
+        }
+
+        Ok(Flow::Next)
+    }
+}
+
+/// The handler for each opcode a program understands, keyed by the opcode's last two
+/// digits.
+///
+/// `OpcodeTable::standard` holds the usual instructions, which can be replaced and
+/// added to before the table is given to `IntcodeProgram::set_opcode_table`.
+#[derive(Clone, Default)]
+pub struct OpcodeTable {
+    handlers: HashMap<usize, Arc<dyn OpcodeHandler>>,
+}
+
+impl OpcodeTable {
+    /// A table with no opcodes at all, not even `hlt`
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// A table with the opcodes of the full Intcode computer
+    pub fn standard() -> Self {
+        let mut table = Self::empty();
+        for (op, _) in MNEMONICS.iter() {
+            table.register(*op, Builtin(*op));
+        }
+        table
+    }
+
+    /// Sets the handler for `opcode`, which must be below 100, returning the handler it
+    /// replaces
+    pub fn register<H: OpcodeHandler + 'static>(
+        &mut self,
+        opcode: usize,
+        handler: H,
+    ) -> Option<Arc<dyn OpcodeHandler>> {
+        assert!(opcode < 100, "opcode {} doesn't fit in two digits", opcode);
+        self.handlers.insert(opcode, Arc::new(handler))
+    }
+
+    pub fn remove(&mut self, opcode: usize) -> Option<Arc<dyn OpcodeHandler>> {
+        self.handlers.remove(&opcode)
+    }
+
+    pub fn get(&self, opcode: usize) -> Option<&dyn OpcodeHandler> {
+        self.handlers.get(&opcode).map(|handler| handler.as_ref())
+    }
+
+    /// Every opcode with a handler, in order
+    pub fn opcodes(&self) -> Vec<usize> {
+        let mut opcodes: Vec<usize> = self.handlers.keys().copied().collect();
+        opcodes.sort_unstable();
+        opcodes
+    }
+
+    /// Finds the handler for the opcode stored at `address`, along with the mode of
+    /// each of its parameters
+    pub fn decode(
+        &self,
+        address: usize,
+        opcode: isize,
+    ) -> Result<(Arc<dyn OpcodeHandler>, Vec<ArgMode>), IntcodeError> {
+        let handler = if opcode < 0 {
+            None
+        } else {
+            self.handlers.get(&((opcode % 100) as usize))
+        }
+        .ok_or(IntcodeError::UnknownOpcode { address, opcode })?;
+
+        let mut arg_modes = vec![MODE_POS; handler.arity()];
+        fill_modes(address, opcode, &mut arg_modes)?;
+        Ok((handler.clone(), arg_modes))
+    }
+
+    /// Splits an opcode into the operation and its parameter modes, as `parse_op` does
+    /// for the standard instructions
+    pub fn parse_op(
+        &self,
+        address: usize,
+        opcode: isize,
+    ) -> Result<(usize, Vec<ArgMode>), IntcodeError> {
+        let (_, arg_modes) = self.decode(address, opcode)?;
+        Ok(((opcode % 100) as usize, arg_modes))
+    }
+}
+
+impl fmt::Debug for OpcodeTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.opcodes()
+                    .into_iter()
+                    .map(|opcode| (opcode, self.handlers[&opcode].mnemonic().to_string())),
+            )
+            .finish()
+    }
+}
+
+/// The opcode table slot on a program. Tables are configuration rather than state, so
+/// they are ignored when comparing programs.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OpcodeSlot(pub(crate) Option<Arc<OpcodeTable>>);
+
+impl PartialEq for OpcodeSlot {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for OpcodeSlot {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assemble, IntcodeResult, OverflowPolicy, MODE_IMM};
+    use std::sync::Mutex;
+
+    /// Prints its parameter to a shared log rather than to the program's output
+    struct DebugPrint(Arc<Mutex<Vec<isize>>>);
+
+    impl OpcodeHandler for DebugPrint {
+        fn mnemonic(&self) -> &str {
+            "dbg"
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn execute(&self, machine: &mut Machine<'_>) -> Result<Flow, IntcodeError> {
+            let value = machine.arg(0)?;
+            self.0.lock().unwrap().push(value);
+            Ok(Flow::Next)
+        }
+    }
+
+    /// Outputs an exit code and halts
+    struct HaltWithCode;
+
+    impl OpcodeHandler for HaltWithCode {
+        fn mnemonic(&self) -> &str {
+            "exit"
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn execute(&self, machine: &mut Machine<'_>) -> Result<Flow, IntcodeError> {
+            let code = machine.arg(0)?;
+            machine.output(code)?;
+            Ok(Flow::Halt)
+        }
+    }
+
+    #[test]
+    fn standard_table_matches_built_in() {
+        let ops = assemble(
+            "
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    arb #4
+                    out rel(-4)
+                    hlt
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+        let mut built_in = IntcodeProgram::new(ops.clone());
+        built_in.push_input(3);
+        let mut handled = IntcodeProgram::new(ops);
+        handled.set_opcode_table(Arc::new(OpcodeTable::standard()));
+        handled.push_input(3);
+
+        assert_eq!(handled.run_to_halt(), built_in.run_to_halt());
+        assert_eq!(handled, built_in);
+        assert_eq!(handled.cycles(), built_in.cycles());
+    }
+
+    #[test]
+    fn standard_table_widens() {
+        // 4 * isize::MAX only stays positive and non-zero once widened
+        let ops = assemble(
+            "
+                    mul #9223372036854775807, #4, [big]
+                    lt [big], #0, [t]
+                    out [t]
+                    jz [big], #end
+                    out #1
+            end:    hlt
+            big:    .data 0
+            t:      .data 0
+            ",
+        )
+        .unwrap();
+        let big = ops.len() - 2;
+        for policy in [OverflowPolicy::Widen, OverflowPolicy::Arbitrary] {
+            let mut built_in = IntcodeProgram::new(ops.clone());
+            built_in.set_overflow_policy(policy);
+            let mut handled = IntcodeProgram::new(ops.clone());
+            handled.set_overflow_policy(policy);
+            handled.set_opcode_table(Arc::new(OpcodeTable::standard()));
+
+            assert_eq!(built_in.run_to_halt(), Ok(vec![0, 1]));
+            assert_eq!(handled.run_to_halt(), Ok(vec![0, 1]));
+            assert_eq!(handled, built_in);
+            assert_eq!(handled.wide_value(big), built_in.wide_value(big));
+        }
+    }
+
+    #[test]
+    fn vendor_opcodes() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut table = OpcodeTable::standard();
+        table.register(50, DebugPrint(log.clone()));
+        table.register(51, HaltWithCode);
+        assert_eq!(table.parse_op(0, 150), Ok((50, vec![MODE_IMM])));
+
+        // dbg #7; dbg [0]; in [9]; exit [9]
+        let mut program = IntcodeProgram::new(vec![150, 7, 50, 0, 3, 9, 51, 9, 99, 0]);
+        program.set_opcode_table(Arc::new(table));
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(*log.lock().unwrap(), vec![7, 150]);
+
+        program.push_input(42);
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(42)));
+        assert_eq!(program.run(), Ok(IntcodeResult::Halt));
+        assert_eq!(program.exec_ptr(), 6);
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        let mut table = OpcodeTable::standard();
+        assert!(table.remove(9).is_some());
+        assert_eq!(format!("{:?}", OpcodeTable::empty()), "{}".to_string());
+
+        let mut program = IntcodeProgram::new(vec![109, 1, 99]);
+        program.set_opcode_table(Arc::new(table));
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::UnknownOpcode {
+                address: 0,
+                opcode: 109
+            })
+        );
+
+        let mut program = IntcodeProgram::new(vec![350, 0, 99]);
+        let mut table = OpcodeTable::empty();
+        table.register(50, HaltWithCode);
+        program.set_opcode_table(Arc::new(table));
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::InvalidArgMode {
+                address: 0,
+                opcode: 350
+            })
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{IntcodeError, IntcodeProgram, IntcodeResult};
+use std::{error::Error, fmt};
+
+/// A node's index in its pipeline, in the order nodes were added
+pub type NodeId = usize;
+
+#[derive(Debug, Clone)]
+struct Node {
+    name: String,
+    program: IntcodeProgram,
+    targets: Vec<NodeId>,
+    outputs: Vec<isize>,
+}
+
+/// How a pipeline run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineStatus {
+    /// Every node halted
+    Completed,
+    /// The listed nodes are waiting for input that no running node can send
+    Deadlock(Vec<NodeId>),
+    /// A node ran out of its step budget or deadline. Running the pipeline again
+    /// carries on from here.
+    BudgetExhausted(NodeId),
+}
+
+/// What one node did during a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeReport {
+    pub name: String,
+    /// Everything the node output, whether or not it was routed anywhere
+    pub outputs: Vec<isize>,
+    pub halted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineReport {
+    pub status: PipelineStatus,
+    /// Indexed by `NodeId`
+    pub nodes: Vec<NodeReport>,
+}
+
+impl PipelineReport {
+    /// The last value a node output, which is usually its answer
+    pub fn last_output(&self, node: NodeId) -> Option<isize> {
+        self.nodes[node].outputs.last().copied()
+    }
+}
+
+/// A node's program failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineError {
+    pub node: NodeId,
+    pub name: String,
+    pub error: IntcodeError,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {} ({}) failed: {}",
+            self.node, self.name, self.error
+        )
+    }
+}
+
+impl Error for PipelineError {}
+
+/// Programs wired together so that each output of a node is sent to the input of every
+/// node it's connected to. Any graph works, including fan-in, fan-out and cycles.
+///
+/// Nodes take turns in the order they were added, each running until it needs input
+/// that hasn't arrived yet.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    nodes: Vec<Node>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, name: &str, program: IntcodeProgram) -> NodeId {
+        self.nodes.push(Node {
+            name: name.to_string(),
+            program,
+            targets: Vec::new(),
+            outputs: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Routes the outputs of `from` to the input of `to`
+    pub fn connect(&mut self, from: NodeId, to: NodeId) {
+        assert!(to < self.nodes.len(), "No node {} to connect to", to);
+        self.nodes[from].targets.push(to);
+    }
+
+    /// Queues an input for a node, such as a setting it reads before anything is sent
+    /// to it
+    pub fn push_input(&mut self, node: NodeId, value: isize) {
+        self.nodes[node].program.push_input(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn program(&self, node: NodeId) -> &IntcodeProgram {
+        &self.nodes[node].program
+    }
+
+    /// Runs the nodes until they have all halted or none of them can make progress
+    pub fn run(&mut self) -> Result<PipelineReport, PipelineError> {
+        let status = loop {
+            // With no outputs in a full round nothing new was sent, so every node left
+            // is stuck waiting for input
+            let mut sent = false;
+            let mut exhausted = None;
+
+            for id in 0..self.nodes.len() {
+                let node = &mut self.nodes[id];
+                if node.program.is_halted() {
+                    continue;
+                }
+
+                let (outputs, result) =
+                    node.program
+                        .run_until_input()
+                        .map_err(|error| PipelineError {
+                            node: id,
+                            name: node.name.clone(),
+                            error,
+                        })?;
+                node.outputs.extend(outputs.iter().copied());
+                sent |= !outputs.is_empty();
+
+                for target in node.targets.clone() {
+                    self.nodes[target]
+                        .program
+                        .extend_inputs(outputs.iter().copied());
+                }
+                if result == IntcodeResult::BudgetExhausted {
+                    exhausted = Some(id);
+                    break;
+                }
+            }
+
+            if let Some(id) = exhausted {
+                break PipelineStatus::BudgetExhausted(id);
+            }
+            if !sent {
+                let waiting: Vec<NodeId> = (0..self.nodes.len())
+                    .filter(|id| !self.nodes[*id].program.is_halted())
+                    .collect();
+                if waiting.is_empty() {
+                    break PipelineStatus::Completed;
+                }
+                break PipelineStatus::Deadlock(waiting);
+            }
+        };
+
+        Ok(PipelineReport {
+            status,
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| NodeReport {
+                    name: node.name.clone(),
+                    outputs: node.outputs.clone(),
+                    halted: node.program.is_halted(),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    fn program(source: &str) -> IntcodeProgram {
+        IntcodeProgram::new(assemble(source).unwrap())
+    }
+
+    /// Outputs double each input, forever
+    fn doubler() -> IntcodeProgram {
+        program(
+            "
+            loop:   in [x]
+                    mul [x], #2, [x]
+                    out [x]
+                    jz #0, #loop
+            x:      .data 0
+            ",
+        )
+    }
+
+    #[test]
+    fn fan_out_and_in() {
+        let mut pipeline = Pipeline::new();
+        let source = pipeline.add_node("source", program("out #3\nout #5\nhlt"));
+        let left = pipeline.add_node("left", doubler());
+        let right = pipeline.add_node("right", doubler());
+        let sum = pipeline.add_node(
+            "sum",
+            program(
+                "
+                loop:   in [a]
+                        in [b]
+                        add [a], [b], [a]
+                        out [a]
+                        jz #0, #loop
+                a:      .data 0
+                b:      .data 0
+                ",
+            ),
+        );
+        pipeline.connect(source, left);
+        pipeline.connect(source, right);
+        pipeline.connect(left, sum);
+        pipeline.connect(right, sum);
+
+        let report = pipeline.run().unwrap();
+        assert_eq!(
+            report.status,
+            PipelineStatus::Deadlock(vec![left, right, sum])
+        );
+        assert!(report.nodes[source].halted);
+        assert_eq!(report.nodes[left].outputs, vec![6, 10]);
+        // Both of left's outputs arrive before right's
+        assert_eq!(report.nodes[sum].outputs, vec![16, 16]);
+        assert_eq!(report.last_output(sum), Some(16));
+    }
+
+    #[test]
+    fn cycle_completes() {
+        // Counts down around a ring of two nodes, each halting once it sees or sends zero
+        let countdown = || {
+            program(
+                "
+                        in [n]
+                        jz [n], #done
+                        add [n], #-1, [n]
+                        out [n]
+                        jnz [n], #0
+                done:   hlt
+                n:      .data 0
+                ",
+            )
+        };
+        let mut pipeline = Pipeline::new();
+        let a = pipeline.add_node("a", countdown());
+        let b = pipeline.add_node("b", countdown());
+        pipeline.connect(a, b);
+        pipeline.connect(b, a);
+        pipeline.push_input(a, 4);
+
+        let report = pipeline.run().unwrap();
+        assert_eq!(report.status, PipelineStatus::Completed);
+        assert_eq!(report.nodes[a].outputs, vec![3, 1]);
+        assert_eq!(report.nodes[b].outputs, vec![2, 0]);
+    }
+
+    #[test]
+    fn deadlock_and_errors() {
+        let mut pipeline = Pipeline::new();
+        let a = pipeline.add_node("a", doubler());
+        let b = pipeline.add_node("b", doubler());
+        pipeline.connect(a, b);
+        pipeline.connect(b, a);
+        let report = pipeline.run().unwrap();
+        assert_eq!(report.status, PipelineStatus::Deadlock(vec![a, b]));
+        assert!(report.nodes.iter().all(|node| node.outputs.is_empty()));
+
+        let mut pipeline = Pipeline::new();
+        pipeline.add_node("ok", program("hlt"));
+        let bad = pipeline.add_node("bad", IntcodeProgram::new(vec![42]));
+        let error = pipeline.run().unwrap_err();
+        assert_eq!(error.node, bad);
+        assert_eq!(error.name, "bad");
+    }
+
+    #[test]
+    fn budget_exhausted() {
+        let mut looping = program("loop: jz #0, #loop");
+        looping.set_step_budget(Some(10));
+        let mut pipeline = Pipeline::new();
+        let node = pipeline.add_node("loop", looping);
+        let report = pipeline.run().unwrap();
+        assert_eq!(report.status, PipelineStatus::BudgetExhausted(node));
+    }
+}
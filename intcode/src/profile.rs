@@ -0,0 +1,211 @@
+use crate::asm::MNEMONICS;
+use std::{collections::HashMap, fmt::Write};
+
+/// How many of the busiest addresses and cells `Profile::report` lists
+pub const REPORT_TOP: usize = 10;
+
+/// Execution statistics gathered while a program runs with its profiler enabled
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Instructions executed, by opcode
+    pub opcodes: HashMap<usize, u64>,
+    /// Instructions executed, by the address they were executed from
+    pub addresses: HashMap<usize, u64>,
+    /// Cells read as instruction parameters, by address
+    pub reads: HashMap<usize, u64>,
+    /// Cells written by instructions, by address
+    pub writes: HashMap<usize, u64>,
+    pub max_memory: usize,
+    /// The lowest and highest relative base set
+    pub relative_base_range: Option<(isize, isize)>,
+    pub inputs: u64,
+    pub outputs: u64,
+}
+
+/// The entries of a count table, busiest first and by address among equals
+fn sorted(counts: &HashMap<usize, u64>) -> Vec<(usize, u64)> {
+    let mut entries: Vec<(usize, u64)> = counts.iter().map(|(k, v)| (*k, *v)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries
+}
+
+fn json_counts(counts: &HashMap<usize, u64>) -> String {
+    let mut entries: Vec<(&usize, &u64)> = counts.iter().collect();
+    entries.sort();
+    let fields: Vec<String> = entries
+        .into_iter()
+        .map(|(key, count)| format!(r#""{}":{}"#, key, count))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+impl Profile {
+    pub(crate) fn count_instruction(&mut self, address: usize, opcode: usize) {
+        *self.opcodes.entry(opcode).or_insert(0) += 1;
+        *self.addresses.entry(address).or_insert(0) += 1;
+    }
+
+    pub(crate) fn count_read(&mut self, address: usize) {
+        *self.reads.entry(address).or_insert(0) += 1;
+    }
+
+    pub(crate) fn count_write(&mut self, address: usize) {
+        *self.writes.entry(address).or_insert(0) += 1;
+    }
+
+    pub(crate) fn see_memory_len(&mut self, len: usize) {
+        self.max_memory = self.max_memory.max(len);
+    }
+
+    pub(crate) fn see_relative_base(&mut self, base: isize) {
+        self.relative_base_range = Some(match self.relative_base_range {
+            Some((low, high)) => (low.min(base), high.max(base)),
+            None => (base, base),
+        });
+    }
+
+    /// The total number of instructions executed
+    pub fn instructions(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    /// A human readable summary, with each table sorted busiest first
+    pub fn report(&self) -> String {
+        let total = self.instructions();
+        let mut report = String::new();
+
+        let _ = writeln!(report, "instructions executed: {}", total);
+        for (opcode, count) in sorted(&self.opcodes) {
+            let mnemonic = MNEMONICS
+                .iter()
+                .find(|(op, _)| *op == opcode)
+                .map_or("?", |(_, mnemonic)| *mnemonic);
+            let _ = writeln!(
+                report,
+                "    {:<4} {:>12} {:>6.2}%",
+                mnemonic,
+                count,
+                count as f64 * 100.0 / total as f64
+            );
+        }
+
+        let tables = [
+            ("hottest addresses", &self.addresses),
+            ("most read cells", &self.reads),
+            ("most written cells", &self.writes),
+        ];
+        for (title, counts) in tables.iter() {
+            let _ = writeln!(report, "{}:", title);
+            for (address, count) in sorted(counts).into_iter().take(REPORT_TOP) {
+                let _ = writeln!(report, "    {:>8} {:>12}", address, count);
+            }
+        }
+
+        let _ = writeln!(report, "max memory: {} cells", self.max_memory);
+        if let Some((low, high)) = self.relative_base_range {
+            let _ = writeln!(report, "relative base: {} to {}", low, high);
+        }
+        let _ = writeln!(report, "inputs: {}, outputs: {}", self.inputs, self.outputs);
+        report
+    }
+
+    /// Renders every statistic as a single JSON object, with tables keyed by opcode or
+    /// address
+    pub fn to_json(&self) -> String {
+        let relative_base = match self.relative_base_range {
+            Some((low, high)) => format!(r#"{{"min":{},"max":{}}}"#, low, high),
+            None => "null".to_string(),
+        };
+        format!(
+            r#"{{"instructions":{},"opcodes":{},"addresses":{},"reads":{},"writes":{},"max_memory":{},"relative_base":{},"inputs":{},"outputs":{}}}"#,
+            self.instructions(),
+            json_counts(&self.opcodes),
+            json_counts(&self.addresses),
+            json_counts(&self.reads),
+            json_counts(&self.writes),
+            self.max_memory,
+            relative_base,
+            self.inputs,
+            self.outputs
+        )
+    }
+}
+
+/// The profile slot on a program. Statistics aren't execution state, so they are
+/// ignored when comparing programs.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Profiler(pub(crate) Option<Box<Profile>>);
+
+impl Profiler {
+    pub(crate) fn record<F: FnOnce(&mut Profile)>(&mut self, f: F) {
+        if let Some(profile) = self.0.as_mut() {
+            f(profile);
+        }
+    }
+}
+
+impl PartialEq for Profiler {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Profiler {}
+
+#[cfg(test)]
+mod test {
+    use crate::{assemble, IntcodeProgram};
+
+    #[test]
+    fn counts() {
+        let ops = assemble(
+            "
+                    arb #-2
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    arb #5
+                    hlt
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+        let mut program = IntcodeProgram::new(ops);
+        program.enable_profiler();
+        program.push_input(3);
+        assert_eq!(program.run_to_halt(), Ok(vec![3, 2, 1]));
+
+        let profile = program.profile().unwrap();
+        assert_eq!(profile.instructions(), 13);
+        assert_eq!(profile.opcodes[&1], 3);
+        assert_eq!(profile.opcodes[&9], 2);
+        assert_eq!(profile.addresses[&4], 3);
+        assert_eq!(profile.addresses.get(&5), None);
+        assert_eq!(profile.writes[&16], 4);
+        // Three reads by `out`, three by `add` and three by `jnz`
+        assert_eq!(profile.reads[&16], 9);
+        assert_eq!(profile.max_memory, 17);
+        assert_eq!(profile.relative_base_range, Some((-2, 3)));
+        assert_eq!((profile.inputs, profile.outputs), (1, 3));
+
+        let report = profile.report();
+        assert!(report.starts_with("instructions executed: 13\n"));
+        assert!(report.contains("    out             3  23.08%\n"));
+        assert!(report.contains("relative base: -2 to 3\n"));
+
+        let json = profile.to_json();
+        assert!(json
+            .starts_with(r#"{"instructions":13,"opcodes":{"1":3,"3":1,"4":3,"5":3,"9":2,"99":1}"#));
+        assert!(json.ends_with(
+            r#""max_memory":17,"relative_base":{"min":-2,"max":3},"inputs":1,"outputs":3}"#
+        ));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut program = IntcodeProgram::new(vec![104, 1, 99]);
+        program.run_to_halt().unwrap();
+        assert_eq!(program.profile(), None);
+    }
+}
@@ -0,0 +1,1527 @@
+use crate::{
+    budget::Budget,
+    device::{DeviceSlot, SharedDevice},
+    history::UndoRecord,
+    instruction::DecodeCache,
+    memory::Memory,
+    opcodes::{Flow, Machine, OpcodeSlot},
+    profile::Profiler,
+    trace::{SharedTracer, TraceSink},
+    BigInt, Instruction, IntcodeError, MemoryKind, OpcodeTable, Profile, Snapshot, TraceEvent,
+};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    fmt, io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+/// Splits an opcode into the operation and the mode of each of its parameters
+pub fn parse_op(address: usize, opcode: isize) -> Result<(usize, Vec<ArgMode>), IntcodeError> {
+    let instruction = Instruction::decode(address, opcode)?;
+    Ok((instruction.opcode(), instruction.modes().to_vec()))
+}
+
+fn lock<T: ?Sized>(device: &Mutex<T>) -> MutexGuard<'_, T> {
+    device.lock().expect("Device lock was poisoned")
+}
+
+pub type ArgMode = u8;
+
+pub const MODE_POS: ArgMode = 0;
+pub const MODE_IMM: ArgMode = 1;
+pub const MODE_REL: ArgMode = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntcodeResult {
+    Suspend(isize),
+    /// The program is paused on an input instruction and will resume from it once
+    /// more input has been pushed and `run` is called again
+    NeedsInput,
+    Halt,
+    /// The step budget or deadline ran out. Execution picks up where it stopped once the
+    /// budget is raised and `run` is called again.
+    BudgetExhausted,
+}
+
+/// What to do when an addition or multiplication doesn't fit in a memory cell.
+///
+/// This covers the relative base and relative mode addresses as well as `add` and
+/// `mul`, so a program behaves the same whichever profile it was built with.
+///
+/// The widening policies keep too-big results at full width, readable with
+/// `IntcodeProgram::wide_value`, while the cell itself holds them wrapped. They can be
+/// added, multiplied, compared and tested by jumps. Addresses, the relative base,
+/// opcodes, inputs and outputs are still `isize`, so using a widened value as any of
+/// those is an overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Stop with `IntcodeError::Overflow`
+    #[default]
+    Error,
+    /// Wrap around, as two's complement hardware would
+    Wrap,
+    /// Widen results up to the range of `i128`, and stop with `IntcodeError::Overflow`
+    /// past that
+    Widen,
+    /// Widen results however big they get
+    Arbitrary,
+}
+
+impl OverflowPolicy {
+    pub(crate) fn widens(self) -> bool {
+        matches!(self, OverflowPolicy::Widen | OverflowPolicy::Arbitrary)
+    }
+}
+
+/// An `add`, `mul`, `lt` or `eq` on values that may be widened
+pub(crate) type WideOp = fn(&BigInt, &BigInt) -> BigInt;
+
+fn wide_add(a: &BigInt, b: &BigInt) -> BigInt {
+    a + b
+}
+
+fn wide_mul(a: &BigInt, b: &BigInt) -> BigInt {
+    a * b
+}
+
+fn wide_less_than(a: &BigInt, b: &BigInt) -> BigInt {
+    BigInt::from((a < b) as isize)
+}
+
+fn wide_equals(a: &BigInt, b: &BigInt) -> BigInt {
+    BigInt::from((a == b) as isize)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntcodeProgram {
+    memory: Memory,
+    exec_ptr: usize,
+    relative_base: isize,
+    halted: bool,
+    overflow: OverflowPolicy,
+    /// The full value of each cell widened past `isize`
+    wide: BTreeMap<usize, BigInt>,
+
+    input: VecDeque<isize>,
+    output: Option<isize>,
+    needs_input: bool,
+
+    tracer: TraceSink,
+    device: DeviceSlot,
+    opcodes: OpcodeSlot,
+    decode_cache: DecodeCache,
+    budget: Budget,
+    profiler: Profiler,
+    /// Collects what the current instruction changes while stepping under a `History`
+    undo: Option<UndoRecord>,
+}
+
+impl IntcodeProgram {
+    pub fn new(ops: Vec<isize>) -> Self {
+        Self::with_memory(ops, MemoryKind::Dense)
+    }
+
+    /// Creates a program whose memory is stored as `kind`
+    pub fn with_memory(ops: Vec<isize>, kind: MemoryKind) -> Self {
+        Self {
+            memory: Memory::new(ops, kind),
+            exec_ptr: 0,
+            relative_base: 0,
+            halted: false,
+            overflow: OverflowPolicy::default(),
+            wide: BTreeMap::new(),
+
+            input: VecDeque::new(),
+            output: None,
+            needs_input: false,
+
+            tracer: TraceSink::default(),
+            device: DeviceSlot::default(),
+            opcodes: OpcodeSlot::default(),
+            decode_cache: DecodeCache::default(),
+            budget: Budget {
+                memory: kind.default_limit(),
+                ..Budget::default()
+            },
+            profiler: Profiler::default(),
+            undo: None,
+        }
+    }
+
+    /// Attaches a tracer to receive an event for every step of execution. Clones of
+    /// this program made afterwards will report to the same tracer.
+    pub fn set_tracer(&mut self, tracer: SharedTracer) {
+        self.tracer = TraceSink::new(Some(tracer));
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = TraceSink::default();
+    }
+
+    /// Connects a device for the program to read input from once its queue is empty,
+    /// and to send output to rather than suspending. Parameters that refer to the
+    /// device's mapped range read and write the device instead of memory.
+    ///
+    /// Input read from the device is recorded like queued input, so a `History` can
+    /// rewind over it, but reads and writes of the mapped range can't be undone.
+    pub fn attach_device(&mut self, device: SharedDevice) {
+        self.device = DeviceSlot::new(device);
+    }
+
+    pub fn detach_device(&mut self) {
+        self.device = DeviceSlot::default();
+    }
+
+    /// Runs every instruction through the handlers in `table` rather than the built in
+    /// instruction set, so that opcodes can be added or replaced. Instructions aren't
+    /// cached while a table is set.
+    pub fn set_opcode_table(&mut self, table: Arc<OpcodeTable>) {
+        self.opcodes = OpcodeSlot(Some(table));
+    }
+
+    /// Goes back to the built in instruction set
+    pub fn clear_opcode_table(&mut self) {
+        self.opcodes = OpcodeSlot(None);
+    }
+
+    pub fn opcode_table(&self) -> Option<&Arc<OpcodeTable>> {
+        self.opcodes.0.as_ref()
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow = policy;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// Turns on caching of decoded instructions by address. This pays off for programs
+    /// that loop, and entries are dropped whenever the cell they were decoded from is
+    /// written to, so self-modifying code still behaves.
+    pub fn enable_decode_cache(&mut self) {
+        if !self.decode_cache.is_enabled() {
+            self.decode_cache = DecodeCache::new(true);
+        }
+    }
+
+    pub fn disable_decode_cache(&mut self) {
+        self.decode_cache = DecodeCache::new(false);
+    }
+
+    /// Limits the program to running `steps` more instructions, or lifts the limit
+    pub fn set_step_budget(&mut self, steps: Option<u64>) {
+        self.budget.steps = steps;
+    }
+
+    /// The number of instructions left before the program stops, if limited
+    pub fn step_budget(&self) -> Option<u64> {
+        self.budget.steps
+    }
+
+    /// Stops the program once `deadline` has passed. The clock is only checked every
+    /// `DEADLINE_CHECK_INTERVAL` instructions, so it may overrun slightly.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.budget.deadline = deadline;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.budget.deadline
+    }
+
+    /// Limits memory to `cells` cells, or lifts the limit. Reading or writing past it
+    /// fails with `IntcodeError::MemoryLimit` rather than growing memory. Programs
+    /// start with `MemoryKind::default_limit`.
+    pub fn set_memory_limit(&mut self, cells: Option<usize>) {
+        self.budget.memory = cells;
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.budget.memory
+    }
+
+    /// The number of instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.budget.cycles
+    }
+
+    /// Starts gathering execution statistics, see `profile`
+    pub fn enable_profiler(&mut self) {
+        if self.profiler.0.is_none() {
+            let mut profile = Profile::default();
+            profile.see_memory_len(self.memory.len());
+            self.profiler = Profiler(Some(Box::new(profile)));
+        }
+    }
+
+    /// The statistics gathered since the profiler was enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profiler.0.as_deref()
+    }
+
+    /// Stops profiling, returning the statistics gathered
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profiler.0.take().map(|profile| *profile)
+    }
+
+    /// Copies everything that isn't execution state, such as the tracer and limits
+    pub(crate) fn copy_settings_from(&mut self, other: &IntcodeProgram) {
+        self.tracer = other.tracer.clone();
+        self.device = other.device.clone();
+        self.opcodes = other.opcodes.clone();
+        self.overflow = other.overflow;
+        self.budget.steps = other.budget.steps;
+        self.budget.deadline = other.budget.deadline;
+        self.budget.memory = other.budget.memory;
+    }
+
+    /// Captures the full execution state, including cells widened past `isize` and the
+    /// overflow policy they depend on, but not other settings such as the tracer. Only
+    /// the non-zero cells of memory are captured, so this stays cheap for sparse paged
+    /// memory.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory_len: self.memory.len(),
+            memory: self.memory.cells().collect(),
+            exec_ptr: self.exec_ptr,
+            relative_base: self.relative_base,
+            halted: self.halted,
+            input: self.input.iter().copied().collect(),
+            overflow: self.overflow,
+            wide: self
+                .wide
+                .iter()
+                .map(|(address, value)| (*address, value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Restores a program from a snapshot, using dense memory
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, IntcodeError> {
+        Self::from_snapshot_with_memory(snapshot, MemoryKind::Dense)
+    }
+
+    /// Restores a program from a snapshot, storing its memory as `kind`. A snapshot
+    /// with more memory than `kind.default_limit()` allows fails with
+    /// `IntcodeError::MemoryLimit`, so an untrusted one can't exhaust memory.
+    pub fn from_snapshot_with_memory(
+        snapshot: Snapshot,
+        kind: MemoryKind,
+    ) -> Result<Self, IntcodeError> {
+        let mut program = Self::with_memory(Vec::new(), kind);
+        let address = snapshot.exec_ptr;
+        program.memory = Memory::from_cells(
+            snapshot.memory_len,
+            snapshot.memory,
+            kind,
+            program.memory_limit(),
+        )
+        .map_err(|target| IntcodeError::MemoryLimit { address, target })?;
+        program.exec_ptr = snapshot.exec_ptr;
+        program.relative_base = snapshot.relative_base;
+        program.halted = snapshot.halted;
+        program.input = snapshot.input.into();
+        program.overflow = snapshot.overflow;
+        program.wide = snapshot.wide.into_iter().collect();
+        Ok(program)
+    }
+
+    /// Queues a value to be read by the program's next input instruction
+    pub fn push_input(&mut self, value: isize) {
+        self.input.push_back(value);
+    }
+
+    /// Queues several values, to be read in the order given
+    pub fn extend_inputs<I: IntoIterator<Item = isize>>(&mut self, values: I) {
+        self.input.extend(values);
+    }
+
+    /// The program's memory, including any cells grown past the original image. Paged
+    /// memory has to be copied out in full, so prefer `memory_cells` or `peek_value`
+    /// for large programs.
+    pub fn memory(&self) -> Cow<'_, [isize]> {
+        self.memory.as_slice()
+    }
+
+    /// The address and value of every non-zero cell, in address order, without filling
+    /// in the gaps of paged memory
+    pub fn memory_cells(&self) -> impl Iterator<Item = (usize, isize)> + '_ {
+        self.memory.cells()
+    }
+
+    /// The number of cells in memory, including any grown past the original image
+    pub fn memory_len(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Up to `count` cells starting at `start`, stopping at the end of memory
+    pub fn memory_window(&self, start: usize, count: usize) -> Vec<isize> {
+        self.memory.window(start, count)
+    }
+
+    pub fn memory_kind(&self) -> MemoryKind {
+        self.memory.kind()
+    }
+
+    /// Reads a memory cell without growing memory to reach it
+    pub fn peek_value(&self, target_location: usize) -> isize {
+        self.memory.get(target_location)
+    }
+
+    /// The full value of a cell widened past `isize` under `OverflowPolicy::Widen` or
+    /// `OverflowPolicy::Arbitrary`. The cell itself holds it wrapped.
+    pub fn wide_value(&self, target_location: usize) -> Option<&BigInt> {
+        self.wide.get(&target_location)
+    }
+
+    /// The address and full value of every cell widened past `isize`, in address order
+    pub fn wide_cells(&self) -> impl Iterator<Item = (usize, &BigInt)> + '_ {
+        self.wide.iter().map(|(address, value)| (*address, value))
+    }
+
+    pub fn exec_ptr(&self) -> usize {
+        self.exec_ptr
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Input values queued but not yet read by the program
+    pub fn pending_input(&self) -> &VecDeque<isize> {
+        &self.input
+    }
+
+    fn get_value(&mut self, target_location: usize) -> isize {
+        self.memory.grow(target_location);
+        self.memory.get(target_location)
+    }
+
+    pub fn set_value(&mut self, target_location: usize, new_value: isize) {
+        self.memory.grow(target_location);
+
+        if let Some(undo) = self.undo.as_mut() {
+            undo.writes
+                .push((target_location, self.memory.get(target_location)));
+        }
+        self.write_memory(target_location, new_value);
+        if self.wide.contains_key(&target_location) {
+            self.set_wide(target_location, None);
+        }
+    }
+
+    fn set_wide(&mut self, target_location: usize, value: Option<BigInt>) {
+        let old_value = match value {
+            Some(value) => self.wide.insert(target_location, value),
+            None => self.wide.remove(&target_location),
+        };
+        if let Some(undo) = self.undo.as_mut() {
+            undo.wide_writes.push((target_location, old_value));
+        }
+    }
+
+    /// Fails if a cell about to be read as `isize` holds a widened value
+    fn check_narrow(&self, target_location: usize) -> Result<(), IntcodeError> {
+        if self.wide.contains_key(&target_location) {
+            Err(IntcodeError::Overflow {
+                address: self.exec_ptr,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_memory(&mut self, target_location: usize, new_value: isize) {
+        self.memory.set(target_location, new_value);
+        self.decode_cache.invalidate(target_location);
+    }
+
+    pub(crate) fn add(&self, a: isize, b: isize) -> Result<isize, IntcodeError> {
+        match self.overflow {
+            OverflowPolicy::Wrap => Ok(a.wrapping_add(b)),
+            _ => a.checked_add(b).ok_or(IntcodeError::Overflow {
+                address: self.exec_ptr,
+            }),
+        }
+    }
+
+    pub(crate) fn mul(&self, a: isize, b: isize) -> Result<isize, IntcodeError> {
+        match self.overflow {
+            OverflowPolicy::Wrap => Ok(a.wrapping_mul(b)),
+            _ => a.checked_mul(b).ok_or(IntcodeError::Overflow {
+                address: self.exec_ptr,
+            }),
+        }
+    }
+
+    fn to_address(&self, target: isize) -> Result<usize, IntcodeError> {
+        if target < 0 {
+            Err(IntcodeError::NegativeAddress {
+                address: self.exec_ptr,
+                target,
+            })
+        } else {
+            Ok(target as usize)
+        }
+    }
+
+    /// Fails if reaching `target` would grow memory past its limit. Device mapped
+    /// addresses never grow memory, so they are always allowed.
+    fn check_limit(&self, target: usize) -> Result<usize, IntcodeError> {
+        match self.budget.memory {
+            Some(limit) if target >= limit && self.device.mapping(target).is_none() => {
+                Err(IntcodeError::MemoryLimit {
+                    address: self.exec_ptr,
+                    target,
+                })
+            }
+            _ => Ok(target),
+        }
+    }
+
+    fn get_target_address(&mut self, ptr: usize, mode: ArgMode) -> Result<usize, IntcodeError> {
+        match mode {
+            MODE_POS => {
+                self.check_narrow(ptr)?;
+                let target = self.get_value(ptr);
+                let target = self.to_address(target)?;
+                self.check_limit(target)
+            }
+            MODE_IMM => Ok(ptr),
+            MODE_REL => {
+                self.check_narrow(ptr)?;
+                let offset = self.get_value(ptr);
+                let target = self.add(offset, self.relative_base)?;
+                let target = self.to_address(target)?;
+                self.check_limit(target)
+            }
+            _ => Err(IntcodeError::InvalidArgMode {
+                address: self.exec_ptr,
+                opcode: self.memory.get(self.exec_ptr),
+            }),
+        }
+    }
+
+    pub(crate) fn get_dest_address(
+        &mut self,
+        ptr: usize,
+        mode: ArgMode,
+    ) -> Result<usize, IntcodeError> {
+        if mode == MODE_IMM {
+            return Err(IntcodeError::ImmediateWrite {
+                address: self.exec_ptr,
+            });
+        }
+
+        self.get_target_address(ptr, mode)
+    }
+
+    pub(crate) fn get_arg(&mut self, ptr: usize, mode: ArgMode) -> Result<isize, IntcodeError> {
+        let target_address = self.get_target_address(ptr, mode)?;
+        self.check_narrow(target_address)?;
+        let value = match self.device.mapping(target_address) {
+            Some(device) => lock(device)
+                .read(target_address)
+                .map_err(|err| self.device_error(err))?,
+            None => self.get_value(target_address),
+        };
+        self.profiler
+            .record(|profile| profile.count_read(target_address));
+        self.tracer.emit(|| TraceEvent::Operand {
+            address: ptr,
+            mode,
+            value,
+        });
+
+        Ok(value)
+    }
+
+    /// Writes the result of an instruction to memory, or to the device mapped there
+    pub(crate) fn store(&mut self, dest: usize, value: isize) -> Result<(), IntcodeError> {
+        self.tracer.emit(|| TraceEvent::Write {
+            address: dest,
+            value,
+        });
+        self.profiler.record(|profile| profile.count_write(dest));
+        match self.device.mapping(dest) {
+            Some(device) => lock(device)
+                .write(dest, value)
+                .map_err(|err| self.device_error(err)),
+            None => {
+                self.set_value(dest, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads a parameter that may hold a widened value
+    pub(crate) fn get_wide_arg(
+        &mut self,
+        ptr: usize,
+        mode: ArgMode,
+    ) -> Result<BigInt, IntcodeError> {
+        let target_address = self.get_target_address(ptr, mode)?;
+        match self.wide.get(&target_address) {
+            Some(value) => {
+                let value = value.clone();
+                self.profiler
+                    .record(|profile| profile.count_read(target_address));
+                Ok(value)
+            }
+            None => self.get_arg(ptr, mode).map(BigInt::from),
+        }
+    }
+
+    /// Whether a jump condition is non-zero, allowing for widened values
+    pub(crate) fn get_condition(
+        &mut self,
+        ptr: usize,
+        mode: ArgMode,
+    ) -> Result<bool, IntcodeError> {
+        if self.wide.is_empty() {
+            Ok(self.get_arg(ptr, mode)? != 0)
+        } else {
+            Ok(!self.get_wide_arg(ptr, mode)?.is_zero())
+        }
+    }
+
+    /// Stores a result that might not fit in a cell. Under the widening policies the
+    /// cell gets the wrapped value and the full value is kept alongside.
+    pub(crate) fn store_wide(&mut self, dest: usize, value: BigInt) -> Result<(), IntcodeError> {
+        let overflow = IntcodeError::Overflow {
+            address: self.exec_ptr,
+        };
+        if let Some(value) = value.to_isize() {
+            return self.store(dest, value);
+        }
+        match self.overflow {
+            OverflowPolicy::Error => return Err(overflow),
+            OverflowPolicy::Wrap => return self.store(dest, value.wrapped()),
+            OverflowPolicy::Widen if value.to_i128().is_none() => return Err(overflow),
+            _ => {}
+        }
+        if self.device.mapping(dest).is_some() {
+            return Err(overflow);
+        }
+
+        self.store(dest, value.wrapped())?;
+        self.set_wide(dest, Some(value));
+        Ok(())
+    }
+
+    /// How an `add`, `mul`, `lt` or `eq` has to run on widened values, if it does.
+    /// Arithmetic widens under the widening policies, and comparisons do once any cell
+    /// holds a widened value.
+    pub(crate) fn wide_operation(&self, opcode: usize) -> Option<WideOp> {
+        match opcode {
+            1 if self.overflow.widens() => Some(wide_add),
+            2 if self.overflow.widens() => Some(wide_mul),
+            7 if !self.wide.is_empty() => Some(wide_less_than),
+            8 if !self.wide.is_empty() => Some(wide_equals),
+            _ => None,
+        }
+    }
+
+    /// Runs a three parameter instruction on values that may be widened
+    fn op_wide(&mut self, arg_modes: [ArgMode; 3], op: WideOp) -> Result<(), IntcodeError> {
+        let ptr = self.exec_ptr + 1;
+        let a = self.get_wide_arg(ptr, arg_modes[0])?;
+        let b = self.get_wide_arg(ptr + 1, arg_modes[1])?;
+
+        let dest = self.get_dest_address(ptr + 2, arg_modes[2])?;
+        self.store_wide(dest, op(&a, &b))?;
+
+        self.exec_ptr += 4;
+        Ok(())
+    }
+
+    fn device_error(&self, err: io::Error) -> IntcodeError {
+        IntcodeError::Device {
+            address: self.exec_ptr,
+            message: err.to_string(),
+        }
+    }
+
+    pub(crate) fn jump(&mut self, target: isize) -> Result<(), IntcodeError> {
+        let to = self.to_address(target)?;
+        let from = self.exec_ptr;
+        self.tracer.emit(|| TraceEvent::Jump { from, to });
+        self.exec_ptr = to;
+
+        Ok(())
+    }
+
+    fn op_add(&mut self, arg_modes: [ArgMode; 3]) -> Result<(), IntcodeError> {
+        if let Some(op) = self.wide_operation(1) {
+            return self.op_wide(arg_modes, op);
+        }
+        let ptr = self.exec_ptr + 1;
+        let a = self.get_arg(ptr, arg_modes[0])?;
+        let b = self.get_arg(ptr + 1, arg_modes[1])?;
+
+        let dest = self.get_dest_address(ptr + 2, arg_modes[2])?;
+        let result = self.add(a, b)?;
+        self.store(dest, result)?;
+
+        self.exec_ptr += 4;
+        Ok(())
+    }
+
+    fn op_mult(&mut self, arg_modes: [ArgMode; 3]) -> Result<(), IntcodeError> {
+        if let Some(op) = self.wide_operation(2) {
+            return self.op_wide(arg_modes, op);
+        }
+        let ptr = self.exec_ptr + 1;
+        let a = self.get_arg(ptr, arg_modes[0])?;
+        let b = self.get_arg(ptr + 1, arg_modes[1])?;
+
+        let dest = self.get_dest_address(ptr + 2, arg_modes[2])?;
+        let result = self.mul(a, b)?;
+        self.store(dest, result)?;
+
+        self.exec_ptr += 4;
+        Ok(())
+    }
+
+    fn op_input(&mut self, arg_modes: [ArgMode; 1]) -> Result<(), IntcodeError> {
+        let ptr = self.exec_ptr + 1;
+        let dest = self.get_dest_address(ptr, arg_modes[0])?;
+
+        let value = match self.take_input()? {
+            Some(value) => value,
+            None => {
+                // Leave the exec pointer on this instruction so it is retried on resume
+                self.needs_input = true;
+                return Ok(());
+            }
+        };
+        self.store(dest, value)?;
+
+        self.exec_ptr += 2;
+        Ok(())
+    }
+
+    /// Reads the next input value from the queue, or else from the device, if there
+    /// is one
+    pub(crate) fn take_input(&mut self) -> Result<Option<isize>, IntcodeError> {
+        let (value, from_device) = match (self.input.pop_front(), &self.device.device) {
+            (Some(value), _) => (value, false),
+            (None, Some(device)) => match lock(device).input() {
+                Ok(Some(value)) => (value, true),
+                Ok(None) => return Ok(None),
+                Err(err) => return Err(self.device_error(err)),
+            },
+            (None, None) => return Ok(None),
+        };
+
+        self.tracer.emit(|| TraceEvent::Input { value });
+        self.profiler.record(|profile| profile.inputs += 1);
+        if let Some(undo) = self.undo.as_mut() {
+            undo.input = Some(value);
+            undo.device_input = from_device;
+        }
+        Ok(Some(value))
+    }
+
+    /// Sends a value output by the current instruction to the device, or else sets it
+    /// to be returned as `IntcodeResult::Suspend`
+    pub(crate) fn emit_output(&mut self, value: isize) -> Result<(), IntcodeError> {
+        self.tracer.emit(|| TraceEvent::Output { value });
+        self.profiler.record(|profile| profile.outputs += 1);
+        match &self.device.device {
+            Some(device) => lock(device)
+                .output(value)
+                .map_err(|err| self.device_error(err)),
+            None => {
+                self.output = Some(value);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn adjust_relative_base(&mut self, offset: isize) -> Result<(), IntcodeError> {
+        self.relative_base = self.add(self.relative_base, offset)?;
+        let base = self.relative_base;
+        self.tracer.emit(|| TraceEvent::RelativeBase { base });
+        self.profiler
+            .record(|profile| profile.see_relative_base(base));
+        Ok(())
+    }
+
+    fn op_output(&mut self, arg_modes: [ArgMode; 1]) -> Result<(), IntcodeError> {
+        let ptr = self.exec_ptr + 1;
+        let output_value = self.get_arg(ptr, arg_modes[0])?;
+        self.emit_output(output_value)?;
+
+        self.exec_ptr += 2;
+        Ok(())
+    }
+
+    fn op_jump_if_true(&mut self, arg_modes: [ArgMode; 2]) -> Result<(), IntcodeError> {
+        let ptr = self.exec_ptr + 1;
+        let condition = self.get_condition(ptr, arg_modes[0])?;
+
+        if condition {
+            // Jump to the designated location
+            let target = self.get_arg(ptr + 1, arg_modes[1])?;
+            self.jump(target)?;
+        } else {
+            // Move on to the next op
+            self.exec_ptr += 3;
+        }
+        Ok(())
+    }
+
+    fn op_jump_if_false(&mut self, arg_modes: [ArgMode; 2]) -> Result<(), IntcodeError> {
+        let ptr = self.exec_ptr + 1;
+        let condition = !self.get_condition(ptr, arg_modes[0])?;
+
+        if condition {
+            // Jump to the designated location
+            let target = self.get_arg(ptr + 1, arg_modes[1])?;
+            self.jump(target)?;
+        } else {
+            // Move on to the next op
+            self.exec_ptr += 3;
+        }
+        Ok(())
+    }
+
+    fn op_less_than(&mut self, arg_modes: [ArgMode; 3]) -> Result<(), IntcodeError> {
+        if let Some(op) = self.wide_operation(7) {
+            return self.op_wide(arg_modes, op);
+        }
+        let ptr = self.exec_ptr + 1;
+        let a = self.get_arg(ptr, arg_modes[0])?;
+        let b = self.get_arg(ptr + 1, arg_modes[1])?;
+
+        let result = if a < b { 1 } else { 0 };
+        let dest = self.get_dest_address(ptr + 2, arg_modes[2])?;
+        self.store(dest, result)?;
+
+        self.exec_ptr += 4;
+        Ok(())
+    }
+
+    fn op_equals(&mut self, arg_modes: [ArgMode; 3]) -> Result<(), IntcodeError> {
+        if let Some(op) = self.wide_operation(8) {
+            return self.op_wide(arg_modes, op);
+        }
+        let ptr = self.exec_ptr + 1;
+        let a = self.get_arg(ptr, arg_modes[0])?;
+        let b = self.get_arg(ptr + 1, arg_modes[1])?;
+
+        let result = if a == b { 1 } else { 0 };
+        let dest = self.get_dest_address(ptr + 2, arg_modes[2])?;
+        self.store(dest, result)?;
+
+        self.exec_ptr += 4;
+        Ok(())
+    }
+
+    fn op_relative_offset(&mut self, arg_modes: [ArgMode; 1]) -> Result<(), IntcodeError> {
+        let ptr = self.exec_ptr + 1;
+        let arg_value = self.get_arg(ptr, arg_modes[0])?;
+        self.adjust_relative_base(arg_value)?;
+
+        self.exec_ptr += 2;
+        Ok(())
+    }
+
+    /// Executes the instruction at the exec pointer, returning its opcode
+    fn run_instruction(&mut self) -> Result<usize, IntcodeError> {
+        // The exec pointer stays on the current instruction until it has completed, so
+        // any error raised while executing it reports the instruction's own address
+        if self.exec_ptr >= self.memory.len() {
+            return Err(IntcodeError::PointerOutOfBounds {
+                address: self.exec_ptr,
+            });
+        }
+        self.check_narrow(self.exec_ptr)?;
+        if let Some(table) = self.opcodes.0.clone() {
+            return self.run_handler(&table);
+        }
+
+        let instruction = self.decode()?;
+        self.tracer.emit(|| TraceEvent::Fetch {
+            address: self.exec_ptr,
+            opcode: instruction.opcode(),
+            arg_modes: instruction.modes().to_vec(),
+        });
+
+        match instruction {
+            Instruction::Add(arg_modes) => self.op_add(arg_modes)?,
+            Instruction::Mul(arg_modes) => self.op_mult(arg_modes)?,
+            Instruction::Input(arg_modes) => self.op_input(arg_modes)?,
+            Instruction::Output(arg_modes) => self.op_output(arg_modes)?,
+            Instruction::JumpIfTrue(arg_modes) => self.op_jump_if_true(arg_modes)?,
+            Instruction::JumpIfFalse(arg_modes) => self.op_jump_if_false(arg_modes)?,
+            Instruction::LessThan(arg_modes) => self.op_less_than(arg_modes)?,
+            Instruction::Equals(arg_modes) => self.op_equals(arg_modes)?,
+            Instruction::AdjustBase(arg_modes) => self.op_relative_offset(arg_modes)?,
+            Instruction::Halt => self.halted = true,
+        }
+
+        Ok(instruction.opcode())
+    }
+
+    /// Executes the instruction at the exec pointer using the handler `table` has for it
+    fn run_handler(&mut self, table: &OpcodeTable) -> Result<usize, IntcodeError> {
+        let opcode = self.memory.get(self.exec_ptr);
+        let (handler, arg_modes) = table.decode(self.exec_ptr, opcode)?;
+        let op = (opcode % 100) as usize;
+        self.tracer.emit(|| TraceEvent::Fetch {
+            address: self.exec_ptr,
+            opcode: op,
+            arg_modes: arg_modes.clone(),
+        });
+
+        let flow = handler.execute(&mut Machine::new(self, &arg_modes))?;
+        match flow {
+            Flow::Next => self.exec_ptr += arg_modes.len() + 1,
+            Flow::Jump(target) => self.jump(target)?,
+            Flow::Halt => self.halted = true,
+            // Leave the exec pointer on this instruction so it is retried on resume
+            Flow::WaitForInput => self.needs_input = true,
+        }
+
+        Ok(op)
+    }
+
+    fn decode(&mut self) -> Result<Instruction, IntcodeError> {
+        if let Some(instruction) = self.decode_cache.get(self.exec_ptr) {
+            return Ok(instruction);
+        }
+
+        let instruction = Instruction::decode(self.exec_ptr, self.memory.get(self.exec_ptr))?;
+        self.decode_cache.insert(self.exec_ptr, instruction);
+        Ok(instruction)
+    }
+
+    /// Executes a single instruction, returning a result if it output a value, paused
+    /// for input, halted the program or could not run within the budget
+    pub fn step(&mut self) -> Result<Option<IntcodeResult>, IntcodeError> {
+        if self.halted {
+            return Ok(Some(IntcodeResult::Halt));
+        }
+        if self.budget.is_exhausted() {
+            return Ok(Some(IntcodeResult::BudgetExhausted));
+        }
+
+        let address = self.exec_ptr;
+        let opcode = self.run_instruction()?;
+        if !self.needs_input {
+            self.budget.count_step();
+            let memory_len = self.memory.len();
+            self.profiler.record(|profile| {
+                profile.count_instruction(address, opcode);
+                profile.see_memory_len(memory_len);
+            });
+        }
+
+        if let Some(output) = self.output.take() {
+            Ok(Some(IntcodeResult::Suspend(output)))
+        } else if self.needs_input {
+            self.needs_input = false;
+            Ok(Some(IntcodeResult::NeedsInput))
+        } else if self.halted {
+            Ok(Some(IntcodeResult::Halt))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Steps like `step`, also returning a record of everything the instruction changed.
+    /// A failed instruction is rolled back before its error is returned.
+    pub(crate) fn step_recorded(
+        &mut self,
+    ) -> Result<(Option<IntcodeResult>, UndoRecord), IntcodeError> {
+        self.undo = Some(UndoRecord {
+            exec_ptr: self.exec_ptr,
+            relative_base: self.relative_base,
+            halted: self.halted,
+            memory_len: self.memory.len(),
+            writes: Vec::new(),
+            wide_writes: Vec::new(),
+            input: None,
+            device_input: false,
+        });
+        let result = self.step();
+        let record = self.undo.take().expect("undo record went missing");
+
+        match result {
+            Ok(result) => Ok((result, record)),
+            Err(err) => {
+                // A failed instruction was never counted, so only its changes go back
+                self.restore(&record);
+                Err(err)
+            }
+        }
+    }
+
+    /// Reverts the changes made by a step recorded with `step_recorded`
+    pub(crate) fn undo(&mut self, record: &UndoRecord) {
+        self.restore(record);
+        self.budget.uncount_step();
+    }
+
+    fn restore(&mut self, record: &UndoRecord) {
+        for (address, old_value) in record.writes.iter().rev() {
+            self.write_memory(*address, *old_value);
+        }
+        for (address, old_value) in record.wide_writes.iter().rev() {
+            match old_value {
+                Some(value) => self.wide.insert(*address, value.clone()),
+                None => self.wide.remove(address),
+            };
+        }
+        self.memory.truncate(record.memory_len);
+        self.exec_ptr = record.exec_ptr;
+        self.relative_base = record.relative_base;
+        self.halted = record.halted;
+        if let Some(value) = record.input {
+            self.input.push_front(value);
+        }
+    }
+
+    pub fn run(&mut self) -> Result<IntcodeResult, IntcodeError> {
+        loop {
+            if let Some(result) = self.step()? {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Runs until the program halts, pauses for input or exhausts its budget, returning
+    /// every value output along the way and what stopped it
+    pub fn run_until_input(&mut self) -> Result<(Vec<isize>, IntcodeResult), IntcodeError> {
+        let mut outputs = Vec::new();
+
+        loop {
+            match self.run()? {
+                IntcodeResult::Suspend(output) => outputs.push(output),
+                stop => return Ok((outputs, stop)),
+            }
+        }
+    }
+
+    /// Runs to completion using only the input already queued, returning every value output
+    pub fn run_to_halt(&mut self) -> Result<Vec<isize>, IntcodeError> {
+        match self.run_until_input()? {
+            (outputs, IntcodeResult::Halt) => Ok(outputs),
+            (_, IntcodeResult::BudgetExhausted) => Err(IntcodeError::BudgetExhausted {
+                address: self.exec_ptr,
+            }),
+            _ => Err(IntcodeError::InputExhausted {
+                address: self.exec_ptr,
+            }),
+        }
+    }
+
+    /// Iterates over the program's outputs, ending when it halts, pauses for input or
+    /// exhausts its budget
+    pub fn outputs(&mut self) -> Outputs<'_> {
+        Outputs {
+            program: self,
+            done: false,
+        }
+    }
+}
+
+pub struct Outputs<'a> {
+    program: &'a mut IntcodeProgram,
+    done: bool,
+}
+
+impl<'a> Iterator for Outputs<'a> {
+    type Item = Result<isize, IntcodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.program.run() {
+            Ok(IntcodeResult::Suspend(output)) => Some(Ok(output)),
+            Ok(_) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Writes memory as a comma separated listing, streaming it so that paged memory is
+/// never filled in
+impl fmt::Display for IntcodeProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cells = self.memory.cells().peekable();
+        for address in 0..self.memory.len() {
+            if address > 0 {
+                write!(f, ",")?;
+            }
+            match cells.peek() {
+                Some((next, value)) if *next == address => {
+                    write!(f, "{}", value)?;
+                    cells.next();
+                }
+                _ => write!(f, "0")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opcodes() {
+        assert_eq!(Ok((2, vec![1, 1, 1])), parse_op(0, 11102));
+        assert_eq!(Ok((1, vec![0, 1, 0])), parse_op(0, 1001));
+        assert_eq!(Ok((2, vec![1, 0, 0])), parse_op(0, 102));
+        assert_eq!(Ok((4, vec![1])), parse_op(0, 104));
+        assert_eq!(Ok((3, vec![0])), parse_op(0, 3));
+        assert_eq!(Ok((9, vec![2])), parse_op(0, 209));
+    }
+
+    #[test]
+    fn bad_opcodes() {
+        assert_eq!(
+            Err(IntcodeError::UnknownOpcode {
+                address: 4,
+                opcode: 42
+            }),
+            parse_op(4, 42)
+        );
+        assert_eq!(
+            Err(IntcodeError::UnknownOpcode {
+                address: 0,
+                opcode: -1
+            }),
+            parse_op(0, -1)
+        );
+        assert_eq!(
+            Err(IntcodeError::InvalidArgMode {
+                address: 2,
+                opcode: 301
+            }),
+            parse_op(2, 301)
+        );
+    }
+
+    #[test]
+    fn memory_grows_on_write() {
+        // Store 7 just past the end of the image, then read it back out
+        let mut program = IntcodeProgram::new(vec![1101, 3, 4, 10, 4, 10, 99]);
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(7)));
+        assert_eq!(program.run(), Ok(IntcodeResult::Halt));
+        assert_eq!(program.memory_len(), 11);
+    }
+
+    #[test]
+    fn memory_grows_on_boundary_read() {
+        // Reading the cell exactly one past the end should see zero rather than panic
+        let mut program = IntcodeProgram::new(vec![4, 3, 99]);
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(0)));
+    }
+
+    #[test]
+    fn memory_limit() {
+        // Dense memory stops at its limit rather than allocating 2^50 cells
+        let ops = vec![1101, 1, 1, 1 << 50, 99];
+        let mut program = IntcodeProgram::new(ops.clone());
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::MemoryLimit {
+                address: 0,
+                target: 1 << 50
+            })
+        );
+        assert_eq!(program.memory_len(), 5);
+
+        let mut paged = IntcodeProgram::with_memory(ops, MemoryKind::Paged);
+        assert_eq!(paged.memory_limit(), None);
+        assert_eq!(paged.run(), Ok(IntcodeResult::Halt));
+
+        // Reads are limited too, and the last cell below the limit can still be used
+        let mut program = IntcodeProgram::new(vec![4, 8, 99]);
+        program.set_memory_limit(Some(8));
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::MemoryLimit {
+                address: 0,
+                target: 8
+            })
+        );
+        program.set_memory_limit(Some(9));
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(0)));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let mut program = IntcodeProgram::new(vec![1, 0, 0, 0, 42]);
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::UnknownOpcode {
+                address: 4,
+                opcode: 42
+            })
+        );
+
+        let mut program = IntcodeProgram::new(vec![11101, 1, 1, 0, 99]);
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::ImmediateWrite { address: 0 })
+        );
+
+        let mut program = IntcodeProgram::new(vec![109, -10, 204, 3, 99]);
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::NegativeAddress {
+                address: 2,
+                target: -7
+            })
+        );
+
+        let mut program = IntcodeProgram::new(vec![1105, 1, -3, 99]);
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::NegativeAddress {
+                address: 0,
+                target: -3
+            })
+        );
+
+        let mut program = IntcodeProgram::new(vec![1101, 1, 1, 0]);
+        assert_eq!(
+            program.run(),
+            Err(IntcodeError::PointerOutOfBounds { address: 4 })
+        );
+    }
+
+    #[test]
+    fn needs_input() {
+        // Add two inputs together and output the sum
+        let mut program = IntcodeProgram::new(vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99]);
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(program.exec_ptr, 0);
+
+        program.push_input(5);
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(program.exec_ptr, 2);
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(program.exec_ptr, 2);
+
+        program.push_input(3);
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(8)));
+        assert_eq!(program.run(), Ok(IntcodeResult::Halt));
+    }
+
+    #[test]
+    fn inputs_are_fifo() {
+        // Output the first input minus the second
+        let ops = vec![
+            3, 100, 3, 101, 1002, 101, -1, 101, 1, 100, 101, 102, 4, 102, 99,
+        ];
+
+        let mut program = IntcodeProgram::new(ops.clone());
+        program.extend_inputs(vec![10, 3]);
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(7)));
+
+        // Values queued before a pause are kept for after it
+        let mut program = IntcodeProgram::new(ops);
+        program.push_input(10);
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        program.push_input(3);
+        assert_eq!(program.run(), Ok(IntcodeResult::Suspend(7)));
+    }
+
+    #[test]
+    fn batch_outputs() {
+        // Output each input doubled, forever
+        let ops = vec![3, 100, 1002, 100, 2, 100, 4, 100, 1105, 1, 0];
+
+        let mut program = IntcodeProgram::new(ops.clone());
+        program.extend_inputs(vec![1, 2, 3]);
+        assert_eq!(
+            program.run_until_input(),
+            Ok((vec![2, 4, 6], IntcodeResult::NeedsInput))
+        );
+        assert_eq!(
+            program.run_to_halt(),
+            Err(IntcodeError::InputExhausted { address: 0 })
+        );
+
+        let mut program = IntcodeProgram::new(ops);
+        program.extend_inputs(vec![5, 6]);
+        let outputs: Result<Vec<isize>, IntcodeError> = program.outputs().collect();
+        assert_eq!(outputs, Ok(vec![10, 12]));
+
+        let mut program = IntcodeProgram::new(vec![104, 1, 104, 2, 99]);
+        assert_eq!(program.run_to_halt(), Ok(vec![1, 2]));
+        assert_eq!(program.run_until_input(), Ok((vec![], IntcodeResult::Halt)));
+    }
+
+    #[test]
+    fn stepping() {
+        let mut program = IntcodeProgram::new(vec![1101, 2, 3, 7, 4, 7, 99, 0]);
+        assert_eq!(program.step(), Ok(None));
+        assert_eq!(program.exec_ptr(), 4);
+        assert_eq!(program.peek_value(7), 5);
+        assert_eq!(program.step(), Ok(Some(IntcodeResult::Suspend(5))));
+        assert_eq!(program.step(), Ok(Some(IntcodeResult::Halt)));
+        assert!(program.is_halted());
+        assert_eq!(program.step(), Ok(Some(IntcodeResult::Halt)));
+
+        // Peeking past the end of memory should not grow it
+        assert_eq!(program.peek_value(100), 0);
+        assert_eq!(program.memory_len(), 8);
+    }
+
+    #[test]
+    fn memory_kinds_behave_the_same() {
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut dense = IntcodeProgram::with_memory(quine.clone(), MemoryKind::Dense);
+        let mut paged = IntcodeProgram::with_memory(quine.clone(), MemoryKind::Paged);
+        assert_eq!(dense.run_to_halt(), Ok(quine.clone()));
+        assert_eq!(paged.run_to_halt(), Ok(quine));
+        assert_eq!(paged.memory_kind(), MemoryKind::Paged);
+        assert_eq!(dense.memory_len(), paged.memory_len());
+        assert_eq!(dense, paged);
+        assert_eq!(dense.to_string(), paged.to_string());
+        assert!(dense.memory_cells().eq(paged.memory_cells()));
+
+        // Running off the end of grown memory fails at the same place
+        let mut paged = IntcodeProgram::with_memory(vec![1105, 1, 5000], MemoryKind::Paged);
+        assert_eq!(
+            paged.run(),
+            Err(IntcodeError::PointerOutOfBounds { address: 5000 })
+        );
+    }
+
+    #[test]
+    fn paged_memory_reaches_huge_addresses() {
+        // Write 7 to address 10^9, then read it back
+        let ops = vec![1101, 3, 4, 1_000_000_000, 4, 1_000_000_000, 99];
+        let mut program = IntcodeProgram::with_memory(ops, MemoryKind::Paged);
+        assert_eq!(program.run_to_halt(), Ok(vec![7]));
+        assert_eq!(program.memory_len(), 1_000_000_001);
+        assert_eq!(program.peek_value(1_000_000_000), 7);
+
+        // Comparing and snapshotting only visit the pages that exist
+        let cells: Vec<(usize, isize)> = program.memory_cells().collect();
+        assert_eq!(cells[cells.len() - 1], (1_000_000_000, 7));
+        assert_eq!(program.snapshot().memory, cells);
+        assert_eq!(program.clone(), program);
+    }
+
+    #[test]
+    fn decode_cache_sees_self_modifying_code() {
+        // The second pass through the loop overwrites the instruction at `patch` with a
+        // halt, which a stale cache entry would miss
+        let ops = crate::assemble(
+            "
+            loop:   out [n]
+                    add [n], #1, [n]
+                    eq [n], #2, [flag]
+                    jz [flag], #patch
+                    add #99, #0, [patch]
+            patch:  add #0, #0, [flag]
+                    jz #0, #loop
+            n:      .data 0
+            flag:   .data 0
+            ",
+        )
+        .unwrap();
+
+        let mut uncached = IntcodeProgram::new(ops.clone());
+        let mut cached = IntcodeProgram::new(ops);
+        cached.enable_decode_cache();
+        let start = cached.clone();
+        assert_eq!(uncached.run_to_halt(), Ok(vec![0, 1]));
+        assert_eq!(cached.run_to_halt(), Ok(vec![0, 1]));
+        assert_eq!(cached, uncached);
+
+        // Undoing the patch has to invalidate the cache too
+        let mut history = crate::History::new(start);
+        while history.run() != Ok(IntcodeResult::Halt) {}
+        assert!(history.rewind_to(0));
+        let mut rewound = history.program().clone();
+        assert_eq!(rewound.run_to_halt(), Ok(vec![0, 1]));
+    }
+
+    #[test]
+    fn overflow_policies() {
+        // The day 9 example stays well inside a 64 bit cell under either policy
+        let mut program = IntcodeProgram::new(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0]);
+        assert_eq!(program.run_to_halt(), Ok(vec![1219070632396864]));
+
+        let ops = vec![1102, isize::MAX, 2, 7, 4, 7, 99, 0];
+        let mut program = IntcodeProgram::new(ops.clone());
+        assert_eq!(program.overflow_policy(), OverflowPolicy::Error);
+        assert_eq!(
+            program.run_to_halt(),
+            Err(IntcodeError::Overflow { address: 0 })
+        );
+
+        let mut program = IntcodeProgram::new(ops);
+        program.set_overflow_policy(OverflowPolicy::Wrap);
+        assert_eq!(program.run_to_halt(), Ok(vec![-2]));
+
+        // Relative base adjustments are covered too
+        let mut program = IntcodeProgram::new(vec![109, isize::MAX, 109, 1, 99]);
+        assert_eq!(program.run(), Err(IntcodeError::Overflow { address: 2 }));
+        let mut program = IntcodeProgram::new(vec![109, isize::MAX, 109, 1, 99]);
+        program.set_overflow_policy(OverflowPolicy::Wrap);
+        assert_eq!(program.run(), Ok(IntcodeResult::Halt));
+        assert_eq!(program.relative_base(), isize::MIN);
+    }
+
+    #[test]
+    fn widening_policies() {
+        // 4 * isize::MAX is positive, but only once widened
+        let ops = crate::assemble(
+            "
+                    mul #9223372036854775807, #4, [big]
+                    lt [big], #0, [t]
+                    out [t]
+                    jz [big], #end
+                    eq [big], #-4, [t]
+                    out [t]
+            end:    hlt
+            big:    .data 0
+            t:      .data 0
+            ",
+        )
+        .unwrap();
+        let big = ops.len() - 2;
+        let run = |policy| {
+            let mut program = IntcodeProgram::new(ops.clone());
+            program.set_overflow_policy(policy);
+            program.run_to_halt().map(|outputs| (outputs, program))
+        };
+        assert_eq!(
+            run(OverflowPolicy::Error).map(|(outputs, _)| outputs),
+            Err(IntcodeError::Overflow { address: 0 })
+        );
+        assert_eq!(run(OverflowPolicy::Wrap).unwrap().0, vec![1, 1]);
+        let (outputs, program) = run(OverflowPolicy::Widen).unwrap();
+        assert_eq!(outputs, vec![0, 0]);
+        assert_eq!(program.peek_value(big), -4);
+        assert_eq!(
+            program.wide_value(big),
+            Some(&BigInt::from(isize::MAX as i128 * 4))
+        );
+
+        // Squaring 2^62 twice fits in an i128 once, but not twice
+        let squares = vec![1102, 1 << 62, 1, 13, 2, 13, 13, 13, 2, 13, 13, 13, 99, 0];
+        let mut program = IntcodeProgram::new(squares.clone());
+        program.set_overflow_policy(OverflowPolicy::Widen);
+        assert_eq!(program.run(), Err(IntcodeError::Overflow { address: 8 }));
+        assert_eq!(
+            program.wide_value(13).map(|value| value.to_string()),
+            Some("21267647932558653966460912964485513216".to_string())
+        );
+
+        let mut program = IntcodeProgram::new(squares);
+        program.set_overflow_policy(OverflowPolicy::Arbitrary);
+        let mut history = crate::History::new(program);
+        assert_eq!(history.run(), Ok(IntcodeResult::Halt));
+        assert_eq!(
+            history.program().wide_value(13).unwrap().to_string(),
+            "452312848583266388373324160190187140051835877600158453279131187530910662656"
+        );
+        // Stepping back puts the earlier wide value back
+        assert!(history.reverse_step());
+        assert!(history.reverse_step());
+        assert_eq!(
+            history.program().wide_value(13).unwrap().to_i128(),
+            Some(1 << 124)
+        );
+        assert!(history.reverse_step());
+        assert_eq!(history.program().wide_value(13), None);
+        assert_eq!(history.program().peek_value(13), 1 << 62);
+
+        // Widened values can't be output, or used as an address or relative base offset
+        for ops in [
+            vec![1102, 1 << 62, 4, 7, 4, 7, 99, 0],
+            vec![1102, 1 << 62, 4, 5, 4, 0, 99],
+            vec![1102, 1 << 62, 4, 7, 9, 7, 99, 0],
+        ] {
+            let mut program = IntcodeProgram::new(ops);
+            program.set_overflow_policy(OverflowPolicy::Arbitrary);
+            assert_eq!(program.run(), Err(IntcodeError::Overflow { address: 4 }));
+        }
+    }
+
+    #[test]
+    fn step_budget() {
+        // An endless loop stops once its budget is spent, and can carry on afterwards
+        let mut program = IntcodeProgram::new(vec![1105, 1, 0]);
+        program.set_step_budget(Some(100));
+        assert_eq!(program.run(), Ok(IntcodeResult::BudgetExhausted));
+        assert_eq!(program.cycles(), 100);
+        assert_eq!(program.run(), Ok(IntcodeResult::BudgetExhausted));
+        program.set_step_budget(Some(50));
+        assert_eq!(program.run(), Ok(IntcodeResult::BudgetExhausted));
+        assert_eq!(program.cycles(), 150);
+        assert_eq!(
+            program.run_to_halt(),
+            Err(IntcodeError::BudgetExhausted { address: 0 })
+        );
+
+        // Running in slices gives the same outputs as running in one go
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut program = IntcodeProgram::new(quine.clone());
+        let mut outputs = Vec::new();
+        loop {
+            program.set_step_budget(Some(7));
+            match program.run_until_input().unwrap() {
+                (mut chunk, IntcodeResult::BudgetExhausted) => outputs.append(&mut chunk),
+                (mut chunk, IntcodeResult::Halt) => {
+                    outputs.append(&mut chunk);
+                    break;
+                }
+                (_, other) => panic!("unexpected stop {:?}", other),
+            }
+        }
+        let mut unlimited = IntcodeProgram::new(quine.clone());
+        assert_eq!(unlimited.run_to_halt(), Ok(quine.clone()));
+        assert_eq!(outputs, quine);
+        assert_eq!(program.cycles(), unlimited.cycles());
+
+        // Waiting for input doesn't use up any cycles
+        let mut program = IntcodeProgram::new(vec![3, 0, 99]);
+        assert_eq!(program.run(), Ok(IntcodeResult::NeedsInput));
+        assert_eq!(program.cycles(), 0);
+    }
+
+    #[test]
+    fn deadline() {
+        let mut program = IntcodeProgram::new(vec![1105, 1, 0]);
+        program.set_deadline(Some(Instant::now()));
+        assert_eq!(program.run(), Ok(IntcodeResult::BudgetExhausted));
+        assert_eq!(program.cycles(), 0);
+
+        program.set_deadline(Some(Instant::now() + std::time::Duration::from_millis(20)));
+        assert_eq!(program.run(), Ok(IntcodeResult::BudgetExhausted));
+        assert!(program.cycles() > 0);
+        assert_eq!(program.cycles() % crate::DEADLINE_CHECK_INTERVAL, 0);
+    }
+}
@@ -0,0 +1,807 @@
+use crate::{BigInt, OverflowPolicy};
+use std::{collections::BTreeMap, convert::TryFrom, error::Error, fmt};
+
+/// Bumped whenever the text or binary encoding changes shape. Version 1, which listed
+/// every memory cell, and version 2, which had no overflow policy or widened cells, can
+/// still be read.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// Runs of non-zero cells closer together than this are written as one run in the text
+/// encoding, zeros included
+const TEXT_RUN_GAP: usize = 8;
+
+const TEXT_HEADER: &str = "intcode-snapshot";
+const BINARY_MAGIC: &[u8; 4] = b"ICSN";
+
+/// How each overflow policy is written, in the order of its binary code
+const POLICY_NAMES: [(OverflowPolicy, &str); 4] = [
+    (OverflowPolicy::Error, "error"),
+    (OverflowPolicy::Wrap, "wrap"),
+    (OverflowPolicy::Widen, "widen"),
+    (OverflowPolicy::Arbitrary, "arbitrary"),
+];
+
+/// The complete execution state of an `IntcodeProgram`, as produced by
+/// `IntcodeProgram::snapshot` and consumed by `IntcodeProgram::from_snapshot`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    /// The number of cells in memory, including any trailing zeros
+    pub memory_len: usize,
+    /// The address and value of every non-zero cell, in address order
+    pub memory: Vec<(usize, isize)>,
+    pub exec_ptr: usize,
+    pub relative_base: isize,
+    pub halted: bool,
+    pub input: Vec<isize>,
+    /// Kept with the state because it decides what the widened cells mean
+    pub overflow: OverflowPolicy,
+    /// The address and full value of every cell widened past `isize`, in address
+    /// order. The cell in `memory` holds the value wrapped.
+    pub wide: Vec<(usize, BigInt)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadHeader,
+    UnsupportedVersion(u32),
+    Truncated,
+    Malformed(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadHeader => write!(f, "data is not an Intcode snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "snapshot version {} is not supported, expected {}",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Truncated => write!(f, "snapshot ended unexpectedly"),
+            SnapshotError::Malformed(message) => write!(f, "malformed snapshot: {}", message),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Cells must be non-zero, in increasing address order and inside the memory length.
+/// Widened cells must also be too big for `isize`, and wrap to what memory holds.
+fn check_cells(snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    let mut next_free = 0;
+    for (address, value) in snapshot.memory.iter() {
+        if *address < next_free || *address >= snapshot.memory_len || *value == 0 {
+            return Err(SnapshotError::Malformed(format!(
+                "bad memory cell [{}] = {}",
+                address, value
+            )));
+        }
+        next_free = address + 1;
+    }
+
+    let mut next_free = 0;
+    for (address, value) in snapshot.wide.iter() {
+        let cell = snapshot
+            .memory
+            .binary_search_by_key(address, |(address, _)| *address)
+            .map_or(0, |index| snapshot.memory[index].1);
+        if *address < next_free
+            || *address >= snapshot.memory_len
+            || value.to_isize().is_some()
+            || value.wrapped() != cell
+        {
+            return Err(SnapshotError::Malformed(format!(
+                "bad widened cell [{}] = {}",
+                address, value
+            )));
+        }
+        next_free = address + 1;
+    }
+    Ok(())
+}
+
+fn policy_name(policy: OverflowPolicy) -> &'static str {
+    POLICY_NAMES
+        .iter()
+        .find(|(named, _)| *named == policy)
+        .map_or("error", |(_, name)| name)
+}
+
+fn parse_policy(value: &str) -> Result<OverflowPolicy, SnapshotError> {
+    POLICY_NAMES
+        .iter()
+        .find(|(_, name)| *name == value)
+        .map(|(policy, _)| *policy)
+        .ok_or_else(|| SnapshotError::Malformed(format!("unknown overflow policy `{}`", value)))
+}
+
+/// Parses `address:value;address:value`
+fn parse_wide(value: &str) -> Result<Vec<(usize, BigInt)>, SnapshotError> {
+    value
+        .split(';')
+        .filter(|cell| !cell.is_empty())
+        .map(|cell| {
+            let bad_cell = || SnapshotError::Malformed(format!("bad widened cell `{}`", cell));
+            let split = cell.find(':').ok_or_else(bad_cell)?;
+            let address = parse_field("widened cell address", &cell[..split])?;
+            let value = BigInt::parse(&cell[split + 1..]).ok_or_else(bad_cell)?;
+            Ok((address, value))
+        })
+        .collect()
+}
+
+fn join(values: &[isize]) -> String {
+    let as_string: Vec<String> = values.iter().map(|num| num.to_string()).collect();
+    as_string.join(",")
+}
+
+fn parse_list(field: &str, value: &str) -> Result<Vec<isize>, SnapshotError> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    value
+        .split(',')
+        .map(|token| {
+            token.parse().map_err(|_| {
+                SnapshotError::Malformed(format!("bad value `{}` in {}", token, field))
+            })
+        })
+        .collect()
+}
+
+/// Groups cells into runs of consecutive values, starting a new run wherever there are
+/// at least `TEXT_RUN_GAP` zeros in a row
+fn to_runs(cells: &[(usize, isize)]) -> Vec<(usize, Vec<isize>)> {
+    let mut runs: Vec<(usize, Vec<isize>)> = Vec::new();
+    for (address, value) in cells.iter() {
+        match runs.last_mut() {
+            Some((start, values)) if *address < *start + values.len() + TEXT_RUN_GAP => {
+                values.resize(address - *start, 0);
+                values.push(*value);
+            }
+            _ => runs.push((*address, vec![*value])),
+        }
+    }
+    runs
+}
+
+/// The non-zero cells of a dense listing starting at `start`
+fn from_dense(start: usize, values: Vec<isize>) -> impl Iterator<Item = (usize, isize)> {
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|(_, value)| *value != 0)
+        .map(move |(offset, value)| (start + offset, value))
+}
+
+/// Parses `start:v,v,...;start:v,...`, checking the runs are in order
+fn parse_runs(value: &str) -> Result<Vec<(usize, isize)>, SnapshotError> {
+    let mut cells = Vec::new();
+    let mut next_free = 0;
+    for run in value.split(';').filter(|run| !run.is_empty()) {
+        let split = run
+            .find(':')
+            .ok_or_else(|| SnapshotError::Malformed(format!("bad memory run `{}`", run)))?;
+        let start: usize = parse_field("memory run start", &run[..split])?;
+        if start < next_free {
+            return Err(SnapshotError::Malformed(format!(
+                "memory run at {} overlaps the one before",
+                start
+            )));
+        }
+        let values = parse_list("memory", &run[split + 1..])?;
+        next_free = start.saturating_add(values.len());
+        cells.extend(from_dense(start, values));
+    }
+    Ok(cells)
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str, value: &str) -> Result<T, SnapshotError> {
+    value
+        .parse()
+        .map_err(|_| SnapshotError::Malformed(format!("bad value `{}` for {}", value, field)))
+}
+
+// Signed values are zigzag encoded so small negatives stay small, then written as
+// LEB128 varints
+fn write_varint(bytes: &mut Vec<u8>, value: isize) {
+    let mut zigzag = ((value << 1) ^ (value >> (isize::BITS - 1))) as usize;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<isize, SnapshotError> {
+    let mut zigzag: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next().ok_or(SnapshotError::Truncated)?;
+        if shift >= usize::BITS {
+            return Err(SnapshotError::Malformed("varint is too long".to_string()));
+        }
+        zigzag |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((zigzag >> 1) as isize) ^ -((zigzag & 1) as isize));
+        }
+        shift += 7;
+    }
+}
+
+fn read_len(bytes: &mut impl Iterator<Item = u8>, field: &str) -> Result<usize, SnapshotError> {
+    let value = read_varint(bytes)?;
+    if value < 0 {
+        return Err(SnapshotError::Malformed(format!("negative {}", field)));
+    }
+    Ok(value as usize)
+}
+
+/// Reads the gap since the cell before, which ended at `next_free`
+fn read_address(
+    bytes: &mut impl Iterator<Item = u8>,
+    next_free: usize,
+) -> Result<usize, SnapshotError> {
+    next_free
+        .checked_add(read_len(bytes, "cell gap")?)
+        .ok_or_else(|| SnapshotError::Malformed("cell address overflows".to_string()))
+}
+
+impl Snapshot {
+    /// A line based encoding that is easy to read and diff by eye. Memory is written as
+    /// runs of cells, each `start:values`, separated by `;`.
+    pub fn to_text(&self) -> String {
+        let runs: Vec<String> = to_runs(&self.memory)
+            .iter()
+            .map(|(start, values)| format!("{}:{}", start, join(values)))
+            .collect();
+        let wide: Vec<String> = self
+            .wide
+            .iter()
+            .map(|(address, value)| format!("{}:{}", address, value))
+            .collect();
+        format!(
+            "{} v{}\noverflow {}\nexec_ptr {}\nrelative_base {}\nhalted {}\ninput {}\n\
+             memory_len {}\nmemory {}\nwide {}\n",
+            TEXT_HEADER,
+            SNAPSHOT_VERSION,
+            policy_name(self.overflow),
+            self.exec_ptr,
+            self.relative_base,
+            self.halted,
+            join(&self.input),
+            self.memory_len,
+            runs.join(";"),
+            wide.join(";")
+        )
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, SnapshotError> {
+        let mut lines = text.lines();
+        let header = lines.next().ok_or(SnapshotError::Truncated)?;
+        let version = header
+            .strip_prefix(TEXT_HEADER)
+            .and_then(|rest| rest.trim().strip_prefix('v'))
+            .ok_or(SnapshotError::BadHeader)?;
+        let version: u32 = parse_field("version", version)?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut snapshot = Snapshot::default();
+        let mut seen = Vec::new();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let (field, value) = match line.find(' ') {
+                Some(split) => (&line[..split], line[split + 1..].trim()),
+                None => (line.trim(), ""),
+            };
+            if seen.contains(&field) {
+                return Err(SnapshotError::Malformed(format!("duplicate {}", field)));
+            }
+            match field {
+                "exec_ptr" => snapshot.exec_ptr = parse_field(field, value)?,
+                "relative_base" => snapshot.relative_base = parse_field(field, value)?,
+                "halted" => snapshot.halted = parse_field(field, value)?,
+                "input" => snapshot.input = parse_list(field, value)?,
+                "memory_len" if version > 1 => snapshot.memory_len = parse_field(field, value)?,
+                "memory" if version == 1 => {
+                    let values = parse_list(field, value)?;
+                    snapshot.memory_len = values.len();
+                    snapshot.memory = from_dense(0, values).collect();
+                }
+                "memory" => snapshot.memory = parse_runs(value)?,
+                "overflow" if version > 2 => snapshot.overflow = parse_policy(value)?,
+                "wide" if version > 2 => snapshot.wide = parse_wide(value)?,
+                _ => {
+                    return Err(SnapshotError::Malformed(format!(
+                        "unknown field `{}`",
+                        field
+                    )))
+                }
+            }
+            seen.push(field);
+        }
+
+        let mut required = vec!["exec_ptr", "relative_base", "halted", "input", "memory"];
+        if version > 1 {
+            required.push("memory_len");
+        }
+        if version > 2 {
+            required.extend(["overflow", "wide"].iter());
+        }
+        for field in required.iter() {
+            if !seen.contains(field) {
+                return Err(SnapshotError::Malformed(format!("missing {}", field)));
+            }
+        }
+        check_cells(&snapshot)?;
+
+        Ok(snapshot)
+    }
+
+    /// A compact binary encoding: a magic number and version byte, the halted flag and
+    /// overflow policy as bytes, then every other field as a zigzag varint. Each memory
+    /// cell is written as the gap since the previous one, then its value. Each widened
+    /// cell is written as the gap, its number of base 2^32 digits, negated for a
+    /// negative value, then the digits.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.memory.len() * 2 + 16);
+        bytes.extend_from_slice(BINARY_MAGIC);
+        bytes.push(SNAPSHOT_VERSION as u8);
+        bytes.push(self.halted as u8);
+        let policy = POLICY_NAMES
+            .iter()
+            .position(|(policy, _)| *policy == self.overflow);
+        bytes.push(policy.unwrap_or(0) as u8);
+        write_varint(&mut bytes, self.exec_ptr as isize);
+        write_varint(&mut bytes, self.relative_base);
+        write_varint(&mut bytes, self.input.len() as isize);
+        for value in self.input.iter() {
+            write_varint(&mut bytes, *value);
+        }
+        write_varint(&mut bytes, self.memory_len as isize);
+        write_varint(&mut bytes, self.memory.len() as isize);
+        let mut next_free = 0;
+        for (address, value) in self.memory.iter() {
+            write_varint(&mut bytes, (address - next_free) as isize);
+            write_varint(&mut bytes, *value);
+            next_free = address + 1;
+        }
+        write_varint(&mut bytes, self.wide.len() as isize);
+        let mut next_free = 0;
+        for (address, value) in self.wide.iter() {
+            let (negative, digits) = value.parts();
+            write_varint(&mut bytes, (address - next_free) as isize);
+            let count = digits.len() as isize;
+            write_varint(&mut bytes, if negative { -count } else { count });
+            for digit in digits {
+                write_varint(&mut bytes, *digit as isize);
+            }
+            next_free = address + 1;
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < BINARY_MAGIC.len() || &bytes[..BINARY_MAGIC.len()] != BINARY_MAGIC {
+            return Err(SnapshotError::BadHeader);
+        }
+        let mut bytes = bytes[BINARY_MAGIC.len()..].iter().copied();
+
+        let version = bytes.next().ok_or(SnapshotError::Truncated)? as u32;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let halted = match bytes.next().ok_or(SnapshotError::Truncated)? {
+            0 => false,
+            1 => true,
+            flag => {
+                return Err(SnapshotError::Malformed(format!(
+                    "bad halted flag {}",
+                    flag
+                )))
+            }
+        };
+        let overflow = if version > 2 {
+            let code = bytes.next().ok_or(SnapshotError::Truncated)?;
+            POLICY_NAMES
+                .get(code as usize)
+                .map(|(policy, _)| *policy)
+                .ok_or_else(|| SnapshotError::Malformed(format!("bad overflow policy {}", code)))?
+        } else {
+            OverflowPolicy::default()
+        };
+        let exec_ptr = read_len(&mut bytes, "exec_ptr")?;
+        let relative_base = read_varint(&mut bytes)?;
+
+        let input_len = read_len(&mut bytes, "input length")?;
+        let input = (0..input_len)
+            .map(|_| read_varint(&mut bytes))
+            .collect::<Result<_, _>>()?;
+        let memory_len = read_len(&mut bytes, "memory length")?;
+        let memory = if version == 1 {
+            let values = (0..memory_len)
+                .map(|_| read_varint(&mut bytes))
+                .collect::<Result<_, _>>()?;
+            from_dense(0, values).collect()
+        } else {
+            let count = read_len(&mut bytes, "cell count")?;
+            let mut next_free: usize = 0;
+            let mut cells = Vec::new();
+            for _ in 0..count {
+                let address = read_address(&mut bytes, next_free)?;
+                cells.push((address, read_varint(&mut bytes)?));
+                next_free = address + 1;
+            }
+            cells
+        };
+        let mut wide = Vec::new();
+        if version > 2 {
+            let count = read_len(&mut bytes, "widened cell count")?;
+            let mut next_free: usize = 0;
+            for _ in 0..count {
+                let address = read_address(&mut bytes, next_free)?;
+                let digit_count = read_varint(&mut bytes)?;
+                let digits = (0..digit_count.unsigned_abs())
+                    .map(|_| {
+                        let digit = read_varint(&mut bytes)?;
+                        u32::try_from(digit).map_err(|_| {
+                            SnapshotError::Malformed(format!("bad widened digit {}", digit))
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                wide.push((address, BigInt::from_parts(digit_count < 0, digits)));
+                next_free = address + 1;
+            }
+        }
+
+        if bytes.next().is_some() {
+            return Err(SnapshotError::Malformed("trailing data".to_string()));
+        }
+
+        let snapshot = Snapshot {
+            memory_len,
+            memory,
+            exec_ptr,
+            relative_base,
+            halted,
+            input,
+            overflow,
+            wide,
+        };
+        check_cells(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Lists every way `other` differs from this snapshot, one line per field or
+    /// memory cell
+    pub fn diff(&self, other: &Snapshot) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.exec_ptr != other.exec_ptr {
+            differences.push(format!("exec_ptr: {} -> {}", self.exec_ptr, other.exec_ptr));
+        }
+        if self.relative_base != other.relative_base {
+            differences.push(format!(
+                "relative_base: {} -> {}",
+                self.relative_base, other.relative_base
+            ));
+        }
+        if self.halted != other.halted {
+            differences.push(format!("halted: {} -> {}", self.halted, other.halted));
+        }
+        if self.overflow != other.overflow {
+            differences.push(format!(
+                "overflow: {} -> {}",
+                policy_name(self.overflow),
+                policy_name(other.overflow)
+            ));
+        }
+        if self.input != other.input {
+            differences.push(format!(
+                "input: [{}] -> [{}]",
+                join(&self.input),
+                join(&other.input)
+            ));
+        }
+        if self.memory_len != other.memory_len {
+            differences.push(format!(
+                "memory_len: {} -> {}",
+                self.memory_len, other.memory_len
+            ));
+        }
+
+        // Memory past the end of either image reads as zero, and widened cells are
+        // compared in full
+        let mut cells: BTreeMap<usize, (BigInt, BigInt)> = BTreeMap::new();
+        for (address, value) in self.memory.iter() {
+            cells.entry(*address).or_default().0 = BigInt::from(*value);
+        }
+        for (address, value) in self.wide.iter() {
+            cells.entry(*address).or_default().0 = value.clone();
+        }
+        for (address, value) in other.memory.iter() {
+            cells.entry(*address).or_default().1 = BigInt::from(*value);
+        }
+        for (address, value) in other.wide.iter() {
+            cells.entry(*address).or_default().1 = value.clone();
+        }
+        for (address, (before, after)) in cells {
+            if before != after {
+                differences.push(format!("[{}]: {} -> {}", address, before, after));
+            }
+        }
+
+        differences
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        IntcodeError, IntcodeProgram, IntcodeResult, MemoryKind, OverflowPolicy,
+        DEFAULT_DENSE_MEMORY_LIMIT,
+    };
+
+    static QUINE: &[isize] = &[
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+    ];
+
+    fn run_outputs(program: &mut IntcodeProgram) -> Vec<isize> {
+        program.run_to_halt().unwrap()
+    }
+
+    #[test]
+    fn resumed_execution_matches() {
+        let expected = run_outputs(&mut IntcodeProgram::new(QUINE.to_vec()));
+
+        for stop_after in [0, 1, 7, 50, 120].iter() {
+            let mut program = IntcodeProgram::new(QUINE.to_vec());
+            let mut outputs = Vec::new();
+            for _ in 0..*stop_after {
+                if let Some(IntcodeResult::Suspend(output)) = program.step().unwrap() {
+                    outputs.push(output);
+                }
+            }
+
+            let snapshot = program.snapshot();
+            let from_text = Snapshot::from_text(&snapshot.to_text()).unwrap();
+            let from_bytes = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+            assert_eq!(from_text, snapshot);
+            assert_eq!(from_bytes, snapshot);
+
+            for restored in [from_text, from_bytes].iter() {
+                let mut restored = IntcodeProgram::from_snapshot(restored.clone()).unwrap();
+                assert_eq!(restored, program);
+
+                let mut resumed_outputs = outputs.clone();
+                resumed_outputs.extend(run_outputs(&mut restored));
+                assert_eq!(resumed_outputs, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn pending_input_is_kept() {
+        let mut program =
+            IntcodeProgram::new(vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]);
+        program.extend_inputs(vec![-40, 2]);
+        program.step().unwrap();
+
+        let text = program.snapshot().to_text();
+        assert_eq!(
+            text,
+            "intcode-snapshot v3
+overflow error
+exec_ptr 2
+relative_base 0
+halted false
+input 2
+memory_len 14
+memory 0:3,11,3,12,1,11,12,13,4,13,99,-40
+wide 
+"
+        );
+
+        // The first version listed every cell
+        let v1 = "intcode-snapshot v1
+exec_ptr 2
+relative_base 0
+halted false
+input 2
+memory 3,11,3,12,1,11,12,13,4,13,99,-40,0,0
+";
+        assert_eq!(Snapshot::from_text(v1), Ok(program.snapshot()));
+        let v2 = "intcode-snapshot v2
+exec_ptr 2
+relative_base 0
+halted false
+input 2
+memory_len 14
+memory 0:3,11,3,12,1,11,12,13,4,13,99,-40
+";
+        assert_eq!(Snapshot::from_text(v2), Ok(program.snapshot()));
+
+        let mut restored =
+            IntcodeProgram::from_snapshot(Snapshot::from_text(&text).unwrap()).unwrap();
+        assert_eq!(restored.run_to_halt(), Ok(vec![-38]));
+    }
+
+    #[test]
+    fn varints() {
+        for value in [
+            0,
+            1,
+            -1,
+            63,
+            -64,
+            64,
+            1 << 40,
+            -(1 << 40),
+            isize::MAX,
+            isize::MIN,
+        ]
+        .iter()
+        {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, *value);
+            assert_eq!(read_varint(&mut bytes.into_iter()), Ok(*value));
+        }
+    }
+
+    #[test]
+    fn bad_snapshots() {
+        assert_eq!(Snapshot::from_text("hello"), Err(SnapshotError::BadHeader));
+        assert_eq!(
+            Snapshot::from_text("intcode-snapshot v4\n"),
+            Err(SnapshotError::UnsupportedVersion(4))
+        );
+        assert_eq!(
+            Snapshot::from_text(
+                "intcode-snapshot v2\nexec_ptr 0\nrelative_base 0\nhalted false\ninput\n\
+                 memory_len 2\nmemory 0:1,2,3\n"
+            ),
+            Err(SnapshotError::Malformed(
+                "bad memory cell [2] = 3".to_string()
+            ))
+        );
+        assert_eq!(
+            Snapshot::from_text(
+                "intcode-snapshot v2\nexec_ptr 0\nrelative_base 0\nhalted false\ninput\n\
+                 memory_len 1\nmemory 0:99\nexec_ptr 5\n"
+            ),
+            Err(SnapshotError::Malformed("duplicate exec_ptr".to_string()))
+        );
+        assert_eq!(
+            Snapshot::from_text("intcode-snapshot v1\nexec_ptr 0\n"),
+            Err(SnapshotError::Malformed(
+                "missing relative_base".to_string()
+            ))
+        );
+
+        let bytes = IntcodeProgram::new(QUINE.to_vec()).snapshot().to_bytes();
+        assert_eq!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        assert_eq!(Snapshot::from_bytes(b"nope"), Err(SnapshotError::BadHeader));
+
+        // A widened cell has to disagree with isize, and wrap to the memory cell
+        for wide in ["2:5", "2:18446744073709551620", "9:18446744073709551616"] {
+            let text = format!(
+                "intcode-snapshot v3\noverflow widen\nexec_ptr 0\nrelative_base 0\n\
+                 halted false\ninput\nmemory_len 3\nmemory 0:99,0,3\nwide {}\n",
+                wide
+            );
+            assert!(Snapshot::from_text(&text).is_err(), "{}", wide);
+        }
+    }
+
+    #[test]
+    fn widened_cells() {
+        // mul #MAX, #4, [20]; lt [20], #0, [21]; out [21]; hlt
+        let mut ops = vec![1102, isize::MAX, 4, 20, 1007, 20, 0, 21, 4, 21, 99];
+        ops.resize(22, 0);
+        let mut program = IntcodeProgram::new(ops);
+        program.set_overflow_policy(OverflowPolicy::Widen);
+        program.step().unwrap();
+
+        let snapshot = program.snapshot();
+        assert_eq!(snapshot.overflow, OverflowPolicy::Widen);
+        assert!(snapshot.to_text().contains("overflow widen\n"));
+        assert!(snapshot
+            .to_text()
+            .contains("wide 20:36893488147419103228\n"));
+        assert_eq!(
+            Snapshot::from_text(&snapshot.to_text()),
+            Ok(snapshot.clone())
+        );
+        assert_eq!(
+            Snapshot::from_bytes(&snapshot.to_bytes()),
+            Ok(snapshot.clone())
+        );
+
+        let mut negative = snapshot.clone();
+        negative.memory.retain(|(address, _)| *address != 20);
+        negative.memory.push((20, 4));
+        negative.wide = vec![(20, BigInt::from(isize::MIN as i128 * 4 + 4))];
+        assert_eq!(
+            Snapshot::from_bytes(&negative.to_bytes()),
+            Ok(negative.clone())
+        );
+        assert_eq!(
+            negative.diff(&snapshot),
+            vec!["[20]: -36893488147419103228 -> 36893488147419103228".to_string()]
+        );
+
+        let mut restored = IntcodeProgram::from_snapshot(snapshot).unwrap();
+        assert_eq!(restored, program);
+        assert_eq!(restored.run_to_halt(), Ok(vec![0]));
+        assert_eq!(program.run_to_halt(), Ok(vec![0]));
+    }
+
+    #[test]
+    fn sparse_memory() {
+        let mut program = IntcodeProgram::with_memory(vec![1101, 3, 4, 5, 99], MemoryKind::Paged);
+        program.set_value(1_000_000_000, 7);
+        program.step().unwrap();
+
+        let snapshot = program.snapshot();
+        assert_eq!(snapshot.memory_len, 1_000_000_001);
+        assert!(snapshot
+            .to_text()
+            .contains("memory 0:1101,3,4,5,99,7;1000000000:7\n"));
+        assert!(snapshot.to_bytes().len() < 64);
+        assert_eq!(
+            Snapshot::from_text(&snapshot.to_text()),
+            Ok(snapshot.clone())
+        );
+        assert_eq!(
+            Snapshot::from_bytes(&snapshot.to_bytes()),
+            Ok(snapshot.clone())
+        );
+
+        // Dense memory would have to allocate every cell below the last one
+        assert_eq!(
+            IntcodeProgram::from_snapshot(snapshot.clone()),
+            Err(IntcodeError::MemoryLimit {
+                address: 4,
+                target: DEFAULT_DENSE_MEMORY_LIMIT
+            })
+        );
+
+        let restored =
+            IntcodeProgram::from_snapshot_with_memory(snapshot, MemoryKind::Paged).unwrap();
+        assert_eq!(restored, program);
+        assert_eq!(restored.peek_value(1_000_000_000), 7);
+    }
+
+    #[test]
+    fn diffs() {
+        let mut program = IntcodeProgram::new(vec![1101, 2, 3, 5, 99]);
+        let before = program.snapshot();
+        program.step().unwrap();
+        let after = program.snapshot();
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                "exec_ptr: 0 -> 4".to_string(),
+                "memory_len: 5 -> 6".to_string(),
+                "[5]: 0 -> 5".to_string()
+            ]
+        );
+        assert!(after.diff(&after).is_empty());
+
+        // Growing memory with zeroes changes nothing but its length
+        let mut longer = after.clone();
+        longer.memory_len += 10;
+        assert_eq!(after.diff(&longer), vec!["memory_len: 6 -> 16".to_string()]);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::ArgMode;
+use std::{
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+/// A single step of an executing program, as reported to a `Tracer`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The instruction at `address` was decoded and is about to execute
+    Fetch {
+        address: usize,
+        opcode: usize,
+        arg_modes: Vec<ArgMode>,
+    },
+    /// The parameter stored at `address` was read in `mode`, resolving to `value`
+    Operand {
+        address: usize,
+        mode: ArgMode,
+        value: isize,
+    },
+    Write {
+        address: usize,
+        value: isize,
+    },
+    Jump {
+        from: usize,
+        to: usize,
+    },
+    RelativeBase {
+        base: isize,
+    },
+    Input {
+        value: isize,
+    },
+    Output {
+        value: isize,
+    },
+}
+
+impl TraceEvent {
+    /// Renders the event as a single line JSON object
+    pub fn to_json(&self) -> String {
+        match self {
+            TraceEvent::Fetch {
+                address,
+                opcode,
+                arg_modes,
+            } => {
+                let modes: Vec<String> = arg_modes.iter().map(|mode| mode.to_string()).collect();
+                format!(
+                    r#"{{"event":"fetch","address":{},"opcode":{},"modes":[{}]}}"#,
+                    address,
+                    opcode,
+                    modes.join(",")
+                )
+            }
+            TraceEvent::Operand {
+                address,
+                mode,
+                value,
+            } => format!(
+                r#"{{"event":"operand","address":{},"mode":{},"value":{}}}"#,
+                address, mode, value
+            ),
+            TraceEvent::Write { address, value } => format!(
+                r#"{{"event":"write","address":{},"value":{}}}"#,
+                address, value
+            ),
+            TraceEvent::Jump { from, to } => {
+                format!(r#"{{"event":"jump","from":{},"to":{}}}"#, from, to)
+            }
+            TraceEvent::RelativeBase { base } => {
+                format!(r#"{{"event":"relative_base","base":{}}}"#, base)
+            }
+            TraceEvent::Input { value } => format!(r#"{{"event":"input","value":{}}}"#, value),
+            TraceEvent::Output { value } => format!(r#"{{"event":"output","value":{}}}"#, value),
+        }
+    }
+}
+
+/// Receives execution events from an `IntcodeProgram` it has been attached to
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+/// Discards every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullTracer;
+
+impl Tracer for NullTracer {
+    fn trace(&mut self, _event: &TraceEvent) {}
+}
+
+/// Keeps every event in memory, mostly useful for assertions in tests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordingTracer {
+    pub events: Vec<TraceEvent>,
+}
+
+impl Tracer for RecordingTracer {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.events.push(event.clone());
+    }
+}
+
+/// Writes each event as a line of JSON
+#[derive(Debug)]
+pub struct JsonLinesTracer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Tracer for JsonLinesTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        // Tracing is best effort, a broken sink should not stop the program
+        let _ = writeln!(self.writer, "{}", event.to_json());
+    }
+}
+
+pub type SharedTracer = Arc<Mutex<dyn Tracer + Send>>;
+
+/// The tracer slot on a program. Clones share the same tracer, and it is ignored when
+/// comparing programs so that traced and untraced runs still compare equal.
+#[derive(Clone, Default)]
+pub(crate) struct TraceSink(Option<SharedTracer>);
+
+impl TraceSink {
+    pub(crate) fn new(tracer: Option<SharedTracer>) -> Self {
+        Self(tracer)
+    }
+
+    pub(crate) fn is_attached(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn emit<F: FnOnce() -> TraceEvent>(&self, event: F) {
+        if let Some(tracer) = &self.0 {
+            let mut tracer = tracer.lock().expect("Tracer lock was poisoned");
+            tracer.trace(&event());
+        }
+    }
+}
+
+impl fmt::Debug for TraceSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_attached() {
+            write!(f, "TraceSink(attached)")
+        } else {
+            write!(f, "TraceSink(none)")
+        }
+    }
+}
+
+impl PartialEq for TraceSink {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for TraceSink {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntcodeProgram;
+
+    #[test]
+    fn records_events() {
+        let recorder = Arc::new(Mutex::new(RecordingTracer::default()));
+        let mut program = IntcodeProgram::new(vec![3, 9, 1001, 9, 5, 9, 4, 9, 99, 0]);
+        program.set_tracer(recorder.clone());
+        program.push_input(2);
+        assert_eq!(program.run_to_halt(), Ok(vec![7]));
+
+        let events = recorder.lock().unwrap().events.clone();
+        assert_eq!(
+            events,
+            vec![
+                TraceEvent::Fetch {
+                    address: 0,
+                    opcode: 3,
+                    arg_modes: vec![0]
+                },
+                TraceEvent::Input { value: 2 },
+                TraceEvent::Write {
+                    address: 9,
+                    value: 2
+                },
+                TraceEvent::Fetch {
+                    address: 2,
+                    opcode: 1,
+                    arg_modes: vec![0, 1, 0]
+                },
+                TraceEvent::Operand {
+                    address: 3,
+                    mode: 0,
+                    value: 2
+                },
+                TraceEvent::Operand {
+                    address: 4,
+                    mode: 1,
+                    value: 5
+                },
+                TraceEvent::Write {
+                    address: 9,
+                    value: 7
+                },
+                TraceEvent::Fetch {
+                    address: 6,
+                    opcode: 4,
+                    arg_modes: vec![0]
+                },
+                TraceEvent::Operand {
+                    address: 7,
+                    mode: 0,
+                    value: 7
+                },
+                TraceEvent::Output { value: 7 },
+                TraceEvent::Fetch {
+                    address: 8,
+                    opcode: 99,
+                    arg_modes: vec![]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_and_relative_base() {
+        let recorder = Arc::new(Mutex::new(RecordingTracer::default()));
+        let mut program = IntcodeProgram::new(vec![109, 4, 1105, 1, 6, 0, 99]);
+        program.set_tracer(recorder.clone());
+        assert_eq!(program.run_to_halt(), Ok(vec![]));
+
+        let events = recorder.lock().unwrap().events.clone();
+        assert!(events.contains(&TraceEvent::RelativeBase { base: 4 }));
+        assert!(events.contains(&TraceEvent::Jump { from: 2, to: 6 }));
+    }
+
+    #[test]
+    fn json_lines() {
+        let writer = Arc::new(Mutex::new(JsonLinesTracer::new(Vec::new())));
+        let mut program = IntcodeProgram::new(vec![104, -3, 99]);
+        program.set_tracer(writer.clone());
+        program.run_to_halt().unwrap();
+        program.clear_tracer();
+
+        let writer = Arc::try_unwrap(writer).ok().unwrap().into_inner().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            r#"{"event":"fetch","address":0,"opcode":4,"modes":[1]}
+{"event":"operand","address":1,"mode":1,"value":-3}
+{"event":"output","value":-3}
+{"event":"fetch","address":2,"opcode":99,"modes":[]}
+"#
+        );
+    }
+
+    #[test]
+    fn traced_programs_compare_equal() {
+        let mut traced = IntcodeProgram::new(vec![104, 1, 99]);
+        traced.set_tracer(Arc::new(Mutex::new(NullTracer)));
+        let untraced = IntcodeProgram::new(vec![104, 1, 99]);
+        assert_eq!(traced, untraced);
+    }
+}